@@ -1,9 +1,10 @@
+use crate::config::OnConflict;
+use chrono::format::{Item, StrftimeItems};
 use chrono::prelude::*;
 use log::info;
 use std::fs;
 use std::io::{self, Result};
-use std::path::Path;
-use trash;
+use std::path::{Path, PathBuf};
 
 fn ensure_directory_exists(dest_path: &Path) -> Result<()> {
     if !dest_path.exists() {
@@ -13,7 +14,48 @@ fn ensure_directory_exists(dest_path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Whether `dest` is `src` itself, e.g. a file already in the folder it would move to.
+fn is_same_file(src: &Path, dest: &Path) -> bool {
+    dest.exists() && fs::canonicalize(src).ok() == fs::canonicalize(dest).ok()
+}
+
+/// Where `src` should go when `dest` may already exist, or `None` to leave it be.
+fn resolve_conflict(src: &Path, dest: PathBuf, on_conflict: OnConflict) -> Option<PathBuf> {
+    if !dest.exists() || is_same_file(src, &dest) {
+        return Some(dest);
+    }
+    match on_conflict {
+        OnConflict::Overwrite => Some(dest),
+        OnConflict::Skip => {
+            info!(
+                "Leaving {} in place, {} already exists",
+                src.display(),
+                dest.display()
+            );
+            None
+        }
+        OnConflict::Rename => Some(numbered(&dest)),
+    }
+}
+
+/// `dest` with the first free number added to its name, as in `report (1).pdf`.
+fn numbered(dest: &Path) -> PathBuf {
+    let stem = dest.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = dest
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| dest.with_file_name(format!("{} ({}){}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
 fn move_or_fallback(src: &Path, dest: &Path) -> Result<()> {
+    if is_same_file(src, dest) {
+        info!("{} is already in place", src.display());
+        return Ok(());
+    }
     match fs::rename(src, dest) {
         Ok(_) => {
             info!("Moved file from {} to {}", src.display(), dest.display());
@@ -33,7 +75,7 @@ fn move_or_fallback(src: &Path, dest: &Path) -> Result<()> {
     }
 }
 
-pub fn move_file(src: &str, dest: &str) -> Result<()> {
+pub fn move_file(src: &str, dest: &str, on_conflict: OnConflict) -> Result<()> {
     let src_path = Path::new(src);
     let mut dest_path = Path::new(dest).to_path_buf();
 
@@ -45,17 +87,22 @@ pub fn move_file(src: &str, dest: &str) -> Result<()> {
         dest_path = dest_path.join(src_path.file_name().unwrap());
     }
     // Try moving the file, falling back to copy and remove if necessary
-    move_or_fallback(src_path, &dest_path)
+    match resolve_conflict(src_path, dest_path, on_conflict) {
+        Some(dest_path) => move_or_fallback(src_path, &dest_path),
+        None => Ok(()),
+    }
 }
 
+/// Moves a file to the trash. On Linux this follows the freedesktop.org trash
+/// spec, using the trash directory of the file's own mount when it isn't on the
+/// same filesystem as the home trash.
 pub fn delete_file(path: &str) -> Result<()> {
-    trash::delete(Path::new(path))
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    trash::delete(Path::new(path)).map_err(|e| io::Error::other(e.to_string()))?;
     info!("Deleted file {}", path);
     Ok(())
 }
 
-pub fn copy_file(src: &str, dest: &str) -> Result<()> {
+pub fn copy_file(src: &str, dest: &str, on_conflict: OnConflict) -> Result<()> {
     let src_path = Path::new(src);
     let mut dest_path = Path::new(dest).to_path_buf();
 
@@ -65,6 +112,14 @@ pub fn copy_file(src: &str, dest: &str) -> Result<()> {
     if dest_path.is_dir() {
         dest_path = dest_path.join(src_path.file_name().unwrap());
     }
+    if is_same_file(src_path, &dest_path) {
+        info!("{} is already in place", src_path.display());
+        return Ok(());
+    }
+    let dest_path = match resolve_conflict(src_path, dest_path, on_conflict) {
+        Some(dest_path) => dest_path,
+        None => return Ok(()),
+    };
     // Copy
     fs::copy(src_path, &dest_path)?;
     info!(
@@ -75,17 +130,236 @@ pub fn copy_file(src: &str, dest: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn sort_file_by_date(src: &str, base_dest: &str, pattern: &str) -> Result<()> {
+/// Checks that `pattern` is a strftime pattern `sort_file_by_date` can format.
+pub fn validate_date_pattern(pattern: &str) -> Result<()> {
+    if StrftimeItems::new(pattern).any(|item| item == Item::Error) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid sort_by_date pattern: {}", pattern),
+        ));
+    }
+    Ok(())
+}
+
+pub fn sort_file_by_date(
+    src: &str,
+    base_dest: &str,
+    pattern: &str,
+    on_conflict: OnConflict,
+) -> Result<()> {
     let src_path = Path::new(src);
     let metadata = fs::metadata(src_path)?;
     let modified_time = metadata.modified()?;
     let datetime: DateTime<Local> = modified_time.into();
 
+    validate_date_pattern(pattern)?;
     let formatted_date = datetime.format(pattern).to_string();
     let dest_path = Path::new(base_dest).join(formatted_date);
 
     ensure_directory_exists(&dest_path)?;
 
     let final_dest = dest_path.join(src_path.file_name().unwrap());
-    move_or_fallback(src_path, &final_dest)
+    match resolve_conflict(src_path, final_dest, on_conflict) {
+        Some(final_dest) => move_or_fallback(src_path, &final_dest),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn move_file_creates_destination_directory() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("report.pdf");
+        fs::write(&src, "report").unwrap();
+        let dest = dir.path().join("Documents/2024");
+
+        move_file(
+            src.to_str().unwrap(),
+            dest.to_str().unwrap(),
+            OnConflict::Overwrite,
+        )
+        .unwrap();
+
+        assert!(!src.exists());
+        assert_eq!(
+            fs::read_to_string(dest.join("report.pdf")).unwrap(),
+            "report"
+        );
+    }
+
+    #[test]
+    fn conflicting_moves_rename_or_skip() {
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("Documents");
+        fs::create_dir(&dest).unwrap();
+        fs::write(dest.join("report.pdf"), "old").unwrap();
+        let src = dir.path().join("report.pdf");
+        let (src_str, dest_str) = (src.to_str().unwrap(), dest.to_str().unwrap());
+
+        fs::write(&src, "skipped").unwrap();
+        move_file(src_str, dest_str, OnConflict::Skip).unwrap();
+        assert!(src.exists());
+        move_file(src_str, dest_str, OnConflict::Rename).unwrap();
+
+        assert!(!src.exists());
+        assert_eq!(fs::read_to_string(dest.join("report.pdf")).unwrap(), "old");
+        assert_eq!(
+            fs::read_to_string(dest.join("report (1).pdf")).unwrap(),
+            "skipped"
+        );
+    }
+
+    #[test]
+    fn copy_file_keeps_source() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("song.mp3");
+        fs::write(&src, "la").unwrap();
+        let dest = dir.path().join("Backup");
+
+        copy_file(
+            src.to_str().unwrap(),
+            dest.to_str().unwrap(),
+            OnConflict::Overwrite,
+        )
+        .unwrap();
+
+        assert!(src.exists());
+        assert!(dest.join("song.mp3").exists());
+    }
+
+    #[test]
+    fn sort_file_by_date_uses_modification_time() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("photo.jpg");
+        fs::write(&src, "").unwrap();
+        let modified: DateTime<Local> = fs::metadata(&src).unwrap().modified().unwrap().into();
+
+        sort_file_by_date(
+            src.to_str().unwrap(),
+            dir.path().to_str().unwrap(),
+            "%Y/%m",
+            OnConflict::Overwrite,
+        )
+        .unwrap();
+
+        let expected = dir
+            .path()
+            .join(modified.format("%Y/%m").to_string())
+            .join("photo.jpg");
+        assert!(expected.exists());
+    }
+
+    #[test]
+    fn sort_file_by_date_rejects_invalid_patterns() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("photo.jpg");
+        fs::write(&src, "").unwrap();
+
+        let err = sort_file_by_date(
+            src.to_str().unwrap(),
+            dir.path().to_str().unwrap(),
+            "%Q%",
+            OnConflict::Overwrite,
+        )
+        .unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(src.exists());
+    }
+
+    /// Runs the test named `test` again in a child process with its own `XDG_DATA_HOME`,
+    /// since changing the variable here would leak into tests running in parallel.
+    /// Returns the data directory when called from that child.
+    #[cfg(target_os = "linux")]
+    fn with_data_home(test: &str) -> Option<PathBuf> {
+        const MARKER: &str = "ORDERLY_TEST_DATA_HOME";
+        if let Some(data_home) = std::env::var_os(MARKER) {
+            return Some(data_home.into());
+        }
+        let dir = tempdir().unwrap();
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([test, "--exact", "--test-threads=1"])
+            .env("XDG_DATA_HOME", dir.path())
+            .env(MARKER, dir.path())
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{}", stdout);
+        assert!(stdout.contains("1 passed"), "{}", stdout);
+        None
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn delete_file_moves_to_freedesktop_trash() {
+        let data_home =
+            match with_data_home("actions::tests::delete_file_moves_to_freedesktop_trash") {
+                Some(data_home) => data_home,
+                None => return,
+            };
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("old.log");
+        fs::write(&src, "").unwrap();
+
+        delete_file(src.to_str().unwrap()).unwrap();
+
+        assert!(!src.exists());
+        assert!(data_home.join("Trash/files/old.log").exists());
+        assert!(data_home.join("Trash/info/old.log.trashinfo").exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn delete_file_uses_the_trash_of_other_mounts() {
+        use std::os::unix::fs::MetadataExt;
+
+        let data_home =
+            match with_data_home("actions::tests::delete_file_uses_the_trash_of_other_mounts") {
+                Some(data_home) => data_home,
+                None => return,
+            };
+        // /dev/shm is usually a tmpfs of its own; skip where it isn't.
+        let dir = match tempfile::tempdir_in("/dev/shm") {
+            Ok(dir) => dir,
+            Err(_) => return,
+        };
+        let device = |path: &Path| fs::metadata(path).unwrap().dev();
+        fs::create_dir_all(&data_home).unwrap();
+        if device(dir.path()) == device(&data_home) {
+            return;
+        }
+        let topdir = dir
+            .path()
+            .ancestors()
+            .take_while(|path| device(path) == device(dir.path()))
+            .last()
+            .unwrap();
+        let name = format!("{}.log", dir.path().file_name().unwrap().to_string_lossy());
+        let src = dir.path().join(&name);
+        fs::write(&src, "").unwrap();
+        let uid = fs::metadata(&src).unwrap().uid();
+        let trash = topdir.join(format!(".Trash-{}", uid));
+        let created_trash = !trash.exists() && !topdir.join(".Trash").exists();
+
+        let result = delete_file(src.to_str().unwrap());
+
+        let trashed = vec![topdir.join(format!(".Trash/{}", uid)), trash.clone()]
+            .into_iter()
+            .find(|trash| trash.join("files").join(&name).exists());
+        if let Some(trash) = &trashed {
+            let _ = fs::remove_file(trash.join("files").join(&name));
+            let _ = fs::remove_file(trash.join("info").join(format!("{}.trashinfo", name)));
+        }
+        if created_trash {
+            let _ = fs::remove_dir_all(&trash);
+        }
+        result.unwrap();
+        assert!(!src.exists());
+        assert!(trashed.is_some());
+        assert!(!data_home.join("Trash/files").join(&name).exists());
+    }
 }
@@ -0,0 +1,437 @@
+use crate::conditions::SizeRange;
+use crate::config::{self, Action, Condition, DateComparison, FolderRule, MatchType, Rule};
+use crate::migrate;
+use crate::patterns;
+use crate::units::Age;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: PathBuf,
+    /// Folder path and rule name the diagnostic refers to, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub files: Vec<PathBuf>,
+    pub errors: usize,
+    pub warnings: usize,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Loads and lints every rule file under `config_path` without touching any watched files.
+pub fn check(config_path: &Path, profile: Option<&str>) -> Report {
+    let (rule_sets, errors) = config::load_all(config_path, profile);
+    let mut files: Vec<PathBuf> = rule_sets.iter().map(|r| r.source.clone()).collect();
+    let mut diagnostics = Vec::new();
+
+    for e in errors {
+        if !files.contains(&e.path) {
+            files.push(e.path.clone());
+        }
+        diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            file: e.path,
+            folder: None,
+            rule: None,
+            line: e.location.map(|(line, _)| line),
+            column: e.location.map(|(_, column)| column),
+            message: e.message,
+        });
+    }
+    for rule_set in &rule_sets {
+        check_rule_set(rule_set, &mut diagnostics);
+    }
+    let mut versioned: Vec<&PathBuf> = rule_sets
+        .iter()
+        .flat_map(|r| std::iter::once(&r.source).chain(&r.included))
+        .collect();
+    versioned.sort();
+    versioned.dedup();
+    for file in versioned {
+        match migrate::file_version(file) {
+            Some(version) if version < migrate::CURRENT_VERSION => diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                file: file.clone(),
+                folder: None,
+                rule: None,
+                line: None,
+                column: None,
+                message: format!(
+                    "Written for rule file version {}; run `orderly migrate` to upgrade it to version {}",
+                    version,
+                    migrate::CURRENT_VERSION
+                ),
+            }),
+            _ => {}
+        }
+    }
+    files.sort();
+
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    Report {
+        files,
+        errors,
+        warnings: diagnostics.len() - errors,
+        diagnostics,
+    }
+}
+
+fn check_rule_set(rule_set: &Rule, diagnostics: &mut Vec<Diagnostic>) {
+    for folder in &rule_set.folders {
+        let mut report = |severity, rule: Option<&FolderRule>, message: String| {
+            diagnostics.push(Diagnostic {
+                severity,
+                file: rule_set.source.clone(),
+                folder: Some(folder.path.clone()),
+                rule: rule.map(|r| r.name.clone()),
+                line: None,
+                column: None,
+                message,
+            })
+        };
+
+        if !Path::new(&folder.path).is_dir() {
+            report(
+                Severity::Error,
+                None,
+                format!("Watched folder does not exist: {}", folder.path),
+            );
+        }
+
+        let rules = folder.rules_by_priority();
+        for (index, rule) in rules.iter().copied().enumerate() {
+            let mut groups = Vec::new();
+            capture_groups(&rule.conditions, &mut groups);
+            for action in &rule.actions {
+                let template = match action {
+                    Action::Move { path, .. }
+                    | Action::Copy { path, .. }
+                    | Action::SortByDate { path, .. } => path.as_str(),
+                    Action::Delete | Action::Continue => "",
+                };
+                for name in patterns::template_names(template) {
+                    if !groups.contains(&name) {
+                        report(
+                            Severity::Error,
+                            Some(rule),
+                            format!(
+                                "`{{{}}}` in {} is not a named group of any regex condition",
+                                name, template
+                            ),
+                        );
+                    }
+                }
+            }
+
+            if let Some(earlier) = rules[..index]
+                .iter()
+                .find(|earlier| shadows(earlier, rule, folder.match_type))
+            {
+                report(
+                    Severity::Warning,
+                    Some(rule),
+                    format!(
+                        "Rule can never fire: every file it matches is taken by '{}' first",
+                        earlier.name
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// Adds the names of the groups captured by `regex` conditions, nested ones included.
+fn capture_groups<'c>(conditions: &'c [Condition], groups: &mut Vec<&'c str>) {
+    for condition in conditions {
+        match condition {
+            Condition::Regex { pattern, .. } => groups.extend(pattern.group_names()),
+            Condition::All { conditions }
+            | Condition::Any { conditions }
+            | Condition::Not { conditions } => capture_groups(conditions, groups),
+            _ => {}
+        }
+    }
+}
+
+/// Whether every file matched by `later` is already claimed by `earlier`.
+fn shadows(earlier: &FolderRule, later: &FolderRule, folder_match: MatchType) -> bool {
+    if !earlier.stops() || earlier.is_scheduled() {
+        return false;
+    }
+    implies(
+        &as_condition(later, folder_match),
+        &as_condition(earlier, folder_match),
+    )
+}
+
+/// A rule's conditions as the single group they are evaluated as.
+fn as_condition(rule: &FolderRule, folder_match: MatchType) -> Condition {
+    let conditions = rule.conditions.clone();
+    match rule.match_type.unwrap_or(folder_match) {
+        MatchType::All => Condition::All { conditions },
+        MatchType::Any => Condition::Any { conditions },
+        MatchType::None => Condition::Not { conditions },
+    }
+}
+
+/// Whether a file passing condition `a` is guaranteed to pass condition `b`.
+fn implies(a: &Condition, b: &Condition) -> bool {
+    match (a, b) {
+        (_, Condition::Always) => true,
+        (_, Condition::All { conditions }) => conditions.iter().all(|b| implies(a, b)),
+        (_, Condition::Any { conditions }) => conditions.iter().any(|b| implies(a, b)),
+        (_, Condition::Not { conditions }) => conditions.iter().all(|b| excludes(a, b)),
+        (Condition::All { conditions }, _) => conditions.iter().any(|a| implies(a, b)),
+        (Condition::Any { conditions }, _) => {
+            !conditions.is_empty() && conditions.iter().all(|a| implies(a, b))
+        }
+        (Condition::Name { value: x }, Condition::Name { value: y }) => x == y,
+        (Condition::Name { value: x }, Condition::NameContains { value: y }) => {
+            x.contains(y.as_str())
+        }
+        (Condition::NameContains { value: x }, Condition::NameContains { value: y }) => {
+            x.contains(y.as_str())
+        }
+        (Condition::Extension { value: x }, Condition::Extension { value: y }) => {
+            x.iter().all(|ext| y.contains(ext))
+        }
+        (Condition::Kind { value: x }, Condition::Kind { value: y }) => {
+            x.iter().all(|kind| y.contains(kind))
+        }
+        (Condition::Size(x), Condition::Size(y)) => {
+            let (x, y) = (SizeRange::new(x), SizeRange::new(y));
+            y.outside.is_empty() && x.outside.is_empty() && y.min <= x.min && x.max <= y.max
+        }
+        (
+            Condition::Regex {
+                pattern: x,
+                target: a,
+            },
+            Condition::Regex {
+                pattern: y,
+                target: b,
+            },
+        ) => x == y && a == b,
+        (
+            Condition::Glob {
+                patterns: x,
+                target: a,
+                ignore_case: i,
+            },
+            Condition::Glob {
+                patterns: y,
+                target: b,
+                ignore_case: j,
+            },
+        ) => {
+            let same = |p: &String, q: &String| p == q || (*j && p.eq_ignore_ascii_case(q));
+            a == b && (!i || *j) && !x.is_empty() && x.iter().all(|p| y.iter().any(|q| same(p, q)))
+        }
+        (Condition::Date(x), Condition::Date(y)) => date_implies(x, y),
+        _ => false,
+    }
+}
+
+/// Whether no file can pass both condition `a` and condition `b`.
+fn excludes(a: &Condition, b: &Condition) -> bool {
+    match (a, b) {
+        (Condition::Not { conditions }, _) => conditions.iter().any(|a| implies(b, a)),
+        (_, Condition::Not { conditions }) => conditions.iter().any(|b| implies(a, b)),
+        (Condition::All { conditions }, _) => conditions.iter().any(|a| excludes(a, b)),
+        (_, Condition::All { conditions }) => conditions.iter().any(|b| excludes(a, b)),
+        (Condition::Any { conditions }, _) => conditions.iter().all(|a| excludes(a, b)),
+        (_, Condition::Any { conditions }) => conditions.iter().all(|b| excludes(a, b)),
+        (Condition::Name { value: x }, Condition::Name { value: y }) => x != y,
+        (Condition::Extension { value: x }, Condition::Extension { value: y }) => {
+            !x.iter().any(|ext| y.contains(ext))
+        }
+        (Condition::Kind { value: x }, Condition::Kind { value: y }) => {
+            !x.iter().any(|kind| y.contains(kind))
+        }
+        (Condition::Size(x), Condition::Size(y)) => {
+            let (x, y) = (SizeRange::new(x), SizeRange::new(y));
+            x.outside.is_empty() && y.outside.is_empty() && (x.max < y.min || y.max < x.min)
+        }
+        _ => false,
+    }
+}
+
+/// Whether every timestamp `x` accepts is accepted by `y`, when both look at the same one.
+fn date_implies(x: &DateComparison, y: &DateComparison) -> bool {
+    let seconds = |age: &Option<Age>| age.as_ref().map(|age| age.seconds);
+    x.time == y.time
+        && (y.is.is_none() || y.is == x.is)
+        && y.before
+            .as_ref()
+            .is_none_or(|y| x.before.as_ref().is_some_and(|x| x.start <= y.start))
+        && y.after
+            .as_ref()
+            .is_none_or(|y| x.after.as_ref().is_some_and(|x| x.end >= y.end))
+        && seconds(&y.older_than).is_none_or(|y| seconds(&x.older_than).is_some_and(|x| x >= y))
+        && seconds(&y.within).is_none_or(|y| seconds(&x.within).is_some_and(|x| x <= y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn rule(yaml: &str) -> FolderRule {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn shadowing_follows_each_match_type() {
+        let pdf = "conditions: [{condition_type: extension, value: [pdf]}]";
+        let pdf_or_doc = "conditions: [{condition_type: extension, value: [pdf]}, {condition_type: extension, value: [doc]}]";
+        let txt = "conditions: [{condition_type: extension, value: [txt]}]";
+        let shadows = |earlier: &str, later: &str, folder: MatchType| {
+            shadows(&rule(earlier), &rule(later), folder)
+        };
+
+        assert!(shadows(pdf, pdf, MatchType::All));
+        assert!(!shadows(pdf, txt, MatchType::All));
+        assert!(shadows(pdf_or_doc, pdf, MatchType::Any));
+        assert!(!shadows(pdf, pdf_or_doc, MatchType::Any));
+        // Under `none`, the earlier rule takes everything but PDFs.
+        assert!(shadows(pdf, pdf_or_doc, MatchType::None));
+        assert!(!shadows(pdf_or_doc, pdf, MatchType::None));
+        assert!(shadows(
+            pdf,
+            &format!("match_type: all\n{}", txt),
+            MatchType::None
+        ));
+        assert!(!shadows(
+            &format!("match_type: any\n{}", pdf),
+            pdf,
+            MatchType::None
+        ));
+        assert!(!shadows(
+            &format!("match_type: any\n{}", pdf),
+            txt,
+            MatchType::None
+        ));
+        assert!(shadows("conditions: []", pdf, MatchType::None));
+        assert!(!shadows(
+            &format!("stop: false\n{}", pdf),
+            pdf,
+            MatchType::All
+        ));
+    }
+
+    #[test]
+    fn nested_groups_sizes_dates_and_globs_imply_their_wider_forms() {
+        let implies = |a: &str, b: &str| {
+            let (a, b): (Condition, Condition) = (
+                serde_yaml::from_str(a).unwrap(),
+                serde_yaml::from_str(b).unwrap(),
+            );
+            implies(&a, &b)
+        };
+        let pdf = "{condition_type: extension, value: [pdf]}";
+        let pdf_or_doc = "{condition_type: any, conditions: [{condition_type: extension, value: [pdf]}, {condition_type: extension, value: [doc]}]}";
+        let small_pdf = format!(
+            "{{condition_type: all, conditions: [{}, {{condition_type: size, less_than: 1 MB}}]}}",
+            pdf
+        );
+
+        assert!(implies(&small_pdf, pdf_or_doc));
+        assert!(!implies(pdf_or_doc, &small_pdf));
+        assert!(implies(
+            pdf,
+            "{condition_type: not, conditions: [{condition_type: extension, value: [txt]}]}"
+        ));
+        assert!(implies(
+            "{condition_type: size, at_least: 2 MB, less_than: 3 MB}",
+            "{condition_type: size, at_least: 1 MB, at_most: 5 MB}"
+        ));
+        assert!(!implies(
+            "{condition_type: size, less_than: 10 MB}",
+            "{condition_type: size, less_than: 1 MB}"
+        ));
+        assert!(implies(
+            "{condition_type: date, older_than: 30d}",
+            "{condition_type: date, older_than: 7d}"
+        ));
+        assert!(!implies(
+            "{condition_type: date, older_than: 7d}",
+            "{condition_type: date, older_than: 30d}"
+        ));
+        assert!(!implies(
+            "{condition_type: date, time: created, older_than: 30d}",
+            "{condition_type: date, older_than: 7d}"
+        ));
+        assert!(implies(
+            "{condition_type: date, before: 2024-01-01}",
+            "{condition_type: date, before: 2024-06-01}"
+        ));
+        assert!(implies(
+            "{condition_type: glob, patterns: ['*.PDF']}",
+            "{condition_type: glob, patterns: ['*.pdf'], ignore_case: true}"
+        ));
+        assert!(!implies(
+            "{condition_type: glob, patterns: ['*.pdf'], ignore_case: true}",
+            "{condition_type: glob, patterns: ['*.pdf']}"
+        ));
+    }
+
+    #[test]
+    fn reports_unknown_placeholders_and_invalid_sort_patterns() {
+        let dir = tempdir().unwrap();
+        let inbox = dir.path().join("inbox");
+        fs::create_dir(&inbox).unwrap();
+        let rules = |action: &str| {
+            format!(
+                "name: x\ndescription: y\nfolders:\n- path: {}\n  rules:\n  - name: r\n    conditions:\n    - condition_type: regex\n      pattern: '(?P<vendor>\\w+)-\\d+'\n    actions:\n    - {}\n",
+                inbox.display(),
+                action
+            )
+        };
+        fs::write(
+            dir.path().join("placeholders.yaml"),
+            rules("{action_type: move, path: 'out/{vendor}/{year}'}"),
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("pattern.yaml"),
+            rules("{action_type: sort_by_date, path: out, pattern: '%Q%'}"),
+        )
+        .unwrap();
+
+        let report = check(dir.path(), None);
+
+        let messages: Vec<_> = report
+            .diagnostics
+            .iter()
+            .map(|d| d.message.as_str())
+            .collect();
+        assert_eq!(report.errors, 2, "{:?}", messages);
+        assert!(messages[0].contains("Invalid sort_by_date pattern: %Q%"));
+        assert!(messages[1].starts_with("`{year}` in "));
+        assert!(messages[1]
+            .ends_with("/out/{vendor}/{year} is not a named group of any regex condition"));
+    }
+}
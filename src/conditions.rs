@@ -1,14 +1,100 @@
+use crate::config::{self, DateComparison, FileKind, FileTime, MatchType, SizeComparison, Target};
+use crate::kinds;
+use crate::patterns::RegexPattern;
+use crate::units::ByteSize;
+use chrono::{DateTime, Local};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::borrow::Cow;
+use std::cell::{OnceCell, RefCell};
+use std::collections::BTreeMap;
+use std::fs::{self, Metadata};
 use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// A file being matched under the folder `root`. Its metadata is read at most once,
+/// however many conditions look at it.
+pub struct Entry<'a> {
+    pub path: &'a Path,
+    pub root: &'a Path,
+    metadata: OnceCell<Option<Metadata>>,
+    mime: OnceCell<Option<String>>,
+    /// Named groups captured by the `regex` conditions that matched.
+    captures: RefCell<BTreeMap<String, String>>,
+}
+
+impl<'a> Entry<'a> {
+    pub fn new(path: &'a Path, root: &'a Path) -> Self {
+        Entry {
+            path,
+            root,
+            metadata: OnceCell::new(),
+            mime: OnceCell::new(),
+            captures: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    /// The MIME type, sniffed from the file's first bytes on first use.
+    pub fn mime(&self) -> Option<&str> {
+        self.mime
+            .get_or_init(|| kinds::detect(self.path))
+            .as_deref()
+    }
+
+    pub fn file_name(&self) -> &str {
+        self.path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+    }
+
+    /// The path below `root`, with `/` between components on every platform.
+    pub fn relative_path(&self) -> String {
+        let relative = self.path.strip_prefix(self.root).unwrap_or(self.path);
+        let components: Vec<_> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect();
+        components.join("/")
+    }
+
+    /// The part of the path a `regex` or `glob` condition looks at.
+    pub fn text(&self, target: Target) -> Cow<'_, str> {
+        match target {
+            Target::Name => Cow::Borrowed(self.file_name()),
+            Target::Stem => Cow::Borrowed(
+                self.path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .unwrap_or_default(),
+            ),
+            Target::Path => Cow::Owned(self.relative_path()),
+        }
+    }
+
+    pub fn into_captures(self) -> BTreeMap<String, String> {
+        self.captures.into_inner()
+    }
+
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata
+            .get_or_init(|| fs::metadata(self.path).ok())
+            .as_ref()
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.metadata().is_some_and(Metadata::is_file)
+    }
+}
 
 pub trait Condition {
-    fn evaluate(&self, path: &Path) -> bool;
+    fn evaluate(&self, entry: &Entry) -> bool;
 }
 
 pub struct Always;
 
 impl Condition for Always {
-    fn evaluate(&self, path: &Path) -> bool {
-        path.is_file()
+    fn evaluate(&self, entry: &Entry) -> bool {
+        entry.is_file()
     }
 }
 
@@ -17,8 +103,8 @@ pub struct NameEquals {
 }
 
 impl Condition for NameEquals {
-    fn evaluate(&self, path: &Path) -> bool {
-        path.file_name().unwrap().to_str().unwrap() == self.name
+    fn evaluate(&self, entry: &Entry) -> bool {
+        entry.file_name() == self.name
     }
 }
 
@@ -27,8 +113,8 @@ pub struct ExtensionIn {
 }
 
 impl Condition for ExtensionIn {
-    fn evaluate(&self, path: &Path) -> bool {
-        if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
+    fn evaluate(&self, entry: &Entry) -> bool {
+        if let Some(ext) = entry.path.extension().and_then(|s| s.to_str()) {
             self.extensions.contains(&ext.to_string())
         } else {
             false
@@ -41,23 +127,475 @@ pub struct NameContains {
 }
 
 impl Condition for NameContains {
-    fn evaluate(&self, path: &Path) -> bool {
-        path.to_str().unwrap().contains(&self.substring)
+    fn evaluate(&self, entry: &Entry) -> bool {
+        entry.file_name().contains(&self.substring)
+    }
+}
+
+pub struct RegexMatch {
+    pub pattern: RegexPattern,
+    pub target: Target,
+}
+
+impl Condition for RegexMatch {
+    fn evaluate(&self, entry: &Entry) -> bool {
+        let text = entry.text(self.target);
+        let captures = match self.pattern.0.captures(&text) {
+            Some(captures) => captures,
+            None => return false,
+        };
+        let mut found = entry.captures.borrow_mut();
+        for name in self.pattern.group_names() {
+            if let Some(group) = captures.name(name) {
+                found.insert(name.to_string(), group.as_str().to_string());
+            }
+        }
+        true
+    }
+}
+
+/// Any of several glob patterns, compiled together into one set.
+pub struct GlobMatch {
+    pub globs: GlobSet,
+    pub target: Target,
+}
+
+impl GlobMatch {
+    pub fn new(patterns: &[String], target: Target, ignore_case: bool) -> Result<Self, String> {
+        let mut globs = GlobSetBuilder::new();
+        for pattern in patterns {
+            let glob = GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .case_insensitive(ignore_case)
+                .build()
+                .map_err(|e| format!("invalid glob: {}", e))?;
+            globs.add(glob);
+        }
+        Ok(GlobMatch {
+            globs: globs.build().map_err(|e| e.to_string())?,
+            target,
+        })
+    }
+}
+
+impl Condition for GlobMatch {
+    fn evaluate(&self, entry: &Entry) -> bool {
+        self.globs.is_match(&*entry.text(self.target))
+    }
+}
+
+pub struct KindIn {
+    pub kinds: Vec<FileKind>,
+}
+
+impl Condition for KindIn {
+    fn evaluate(&self, entry: &Entry) -> bool {
+        entry.is_file()
+            && entry
+                .mime()
+                .and_then(kinds::kind_of)
+                .is_some_and(|kind| self.kinds.contains(&kind))
+    }
+}
+
+/// The sizes a `size` condition accepts: those between `min` and `max`, and in none of
+/// the `outside` ranges. All bounds are inclusive.
+#[derive(Debug, PartialEq)]
+pub struct SizeRange {
+    pub min: u64,
+    pub max: u64,
+    pub outside: Vec<(u64, u64)>,
+}
+
+impl SizeRange {
+    pub fn new(compare: &SizeComparison) -> SizeRange {
+        let mut range = SizeRange {
+            min: 0,
+            max: u64::MAX,
+            outside: Vec::new(),
+        };
+        let bytes = |size: &Option<ByteSize>| size.as_ref().map(|s| s.bytes);
+        if let Some(is) = bytes(&compare.is) {
+            range.limit(is, is);
+        }
+        if let Some(less_than) = bytes(&compare.less_than) {
+            match less_than.checked_sub(1) {
+                Some(max) => range.limit(0, max),
+                None => range.limit(1, 0),
+            }
+        }
+        if let Some(greater_than) = bytes(&compare.greater_than) {
+            match greater_than.checked_add(1) {
+                Some(min) => range.limit(min, u64::MAX),
+                None => range.limit(1, 0),
+            }
+        }
+        if let Some(at_most) = bytes(&compare.at_most) {
+            range.limit(0, at_most);
+        }
+        if let Some(at_least) = bytes(&compare.at_least) {
+            range.limit(at_least, u64::MAX);
+        }
+        if let Some(between) = &compare.between {
+            range.limit(between.low.bytes, between.high.bytes);
+        }
+        if let Some(is_not) = bytes(&compare.is_not) {
+            range.outside.push((is_not, is_not));
+        }
+        if let Some(not_between) = &compare.not_between {
+            range
+                .outside
+                .push((not_between.low.bytes, not_between.high.bytes));
+        }
+        range
+    }
+
+    fn limit(&mut self, min: u64, max: u64) {
+        self.min = self.min.max(min);
+        self.max = self.max.min(max);
+    }
+
+    pub fn contains(&self, size: u64) -> bool {
+        self.min <= size
+            && size <= self.max
+            && !self
+                .outside
+                .iter()
+                .any(|&(low, high)| low <= size && size <= high)
+    }
+}
+
+impl Condition for SizeRange {
+    fn evaluate(&self, entry: &Entry) -> bool {
+        match entry.metadata() {
+            Some(metadata) if metadata.is_file() => self.contains(metadata.len()),
+            _ => false,
+        }
+    }
+}
+
+/// Compares one of a file's timestamps. Files without that timestamp never match.
+pub struct DateMatch {
+    pub compare: DateComparison,
+}
+
+impl DateMatch {
+    fn timestamp(&self, metadata: &Metadata) -> Option<SystemTime> {
+        match self.compare.time {
+            FileTime::Modified => metadata.modified().ok(),
+            FileTime::Created => metadata.created().ok(),
+            FileTime::Accessed => metadata.accessed().ok(),
+            FileTime::Added => added(metadata),
+        }
+    }
+
+    pub fn matches(&self, time: SystemTime, now: SystemTime) -> bool {
+        let compare = &self.compare;
+        let age = now.duration_since(time).unwrap_or_default();
+        if let Some(older_than) = &compare.older_than {
+            if age <= Duration::from_secs(older_than.seconds) {
+                return false;
+            }
+        }
+        if let Some(within) = &compare.within {
+            if age > Duration::from_secs(within.seconds) {
+                return false;
+            }
+        }
+        let local = DateTime::<Local>::from(time).naive_local();
+        compare
+            .is
+            .as_ref()
+            .is_none_or(|is| is.start <= local && local < is.end)
+            && compare
+                .before
+                .as_ref()
+                .is_none_or(|before| local < before.start)
+            && compare
+                .after
+                .as_ref()
+                .is_none_or(|after| after.end <= local)
     }
 }
 
-pub fn create_condition(condition_type: &str, value: &str) -> Box<dyn Condition> {
-    match condition_type {
-        "always" => Box::new(Always),
-        "name" => Box::new(NameEquals {
+#[cfg(unix)]
+fn added(metadata: &Metadata) -> Option<SystemTime> {
+    use std::convert::TryFrom;
+    use std::os::unix::fs::MetadataExt;
+    let seconds = u64::try_from(metadata.ctime()).ok()?;
+    let nanos = u32::try_from(metadata.ctime_nsec()).ok()?;
+    Some(SystemTime::UNIX_EPOCH + Duration::new(seconds, nanos))
+}
+
+#[cfg(not(unix))]
+fn added(metadata: &Metadata) -> Option<SystemTime> {
+    metadata.created().ok()
+}
+
+impl Condition for DateMatch {
+    fn evaluate(&self, entry: &Entry) -> bool {
+        match entry.metadata() {
+            Some(metadata) if metadata.is_file() => self
+                .timestamp(metadata)
+                .is_some_and(|time| self.matches(time, SystemTime::now())),
+            _ => false,
+        }
+    }
+}
+
+/// Combines conditions according to a match type, short-circuiting where possible.
+pub struct Group {
+    pub match_type: MatchType,
+    pub conditions: Vec<Box<dyn Condition>>,
+}
+
+impl Condition for Group {
+    fn evaluate(&self, entry: &Entry) -> bool {
+        let mut conditions = self.conditions.iter();
+        match self.match_type {
+            MatchType::All => conditions.all(|c| c.evaluate(entry)),
+            MatchType::Any => conditions.any(|c| c.evaluate(entry)),
+            MatchType::None => !conditions.any(|c| c.evaluate(entry)),
+        }
+    }
+}
+
+/// Builds the conditions of a rule, compiling their patterns. Done once per rule, not
+/// per file.
+pub fn create_group(
+    conditions: &[config::Condition],
+    match_type: MatchType,
+) -> Result<Group, String> {
+    Ok(Group {
+        match_type,
+        conditions: conditions
+            .iter()
+            .map(create_condition)
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+pub fn create_condition(condition: &config::Condition) -> Result<Box<dyn Condition>, String> {
+    Ok(match condition {
+        config::Condition::Always => Box::new(Always),
+        config::Condition::Name { value } => Box::new(NameEquals {
             name: value.to_string(),
         }),
-        "extension" => Box::new(ExtensionIn {
-            extensions: value.split(',').map(|s| s.trim().to_string()).collect(),
+        config::Condition::Extension { value } => Box::new(ExtensionIn {
+            extensions: value.clone(),
         }),
-        "name_contains" => Box::new(NameContains {
+        config::Condition::NameContains { value } => Box::new(NameContains {
             substring: value.to_string(),
         }),
-        _ => panic!("Unknown condition type: {}", condition_type),
+        config::Condition::Regex { pattern, target } => Box::new(RegexMatch {
+            pattern: pattern.clone(),
+            target: *target,
+        }),
+        config::Condition::Glob {
+            patterns,
+            target,
+            ignore_case,
+        } => Box::new(GlobMatch::new(patterns, *target, *ignore_case)?),
+        config::Condition::Kind { value } => Box::new(KindIn {
+            kinds: value.clone(),
+        }),
+        config::Condition::Size(compare) => Box::new(SizeRange::new(compare)),
+        config::Condition::Date(compare) => Box::new(DateMatch {
+            compare: (**compare).clone(),
+        }),
+        config::Condition::All { conditions } => {
+            Box::new(create_group(conditions, MatchType::All)?)
+        }
+        config::Condition::Any { conditions } => {
+            Box::new(create_group(conditions, MatchType::Any)?)
+        }
+        config::Condition::Not { conditions } => {
+            Box::new(create_group(conditions, MatchType::None)?)
+        }
+        config::Condition::Set { name } => {
+            unreachable!("condition set `{}` is resolved when the config loads", name)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use chrono::TimeZone;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn groups_nest_all_any_and_not() {
+        let root = Path::new("/downloads");
+        let conditions: Vec<config::Condition> = serde_yaml::from_str(
+            "
+- condition_type: any
+  conditions:
+  - {condition_type: extension, value: [pdf]}
+  - {condition_type: extension, value: [docx]}
+- {condition_type: name_contains, value: invoice}
+- condition_type: not
+  conditions:
+  - condition_type: all
+    conditions:
+    - {condition_type: name_contains, value: draft}
+    - {condition_type: extension, value: [docx]}
+",
+        )
+        .unwrap();
+        let group = |match_type| create_group(&conditions, match_type).unwrap();
+        let matches =
+            |group: &Group, name: &str| group.evaluate(&Entry::new(&root.join(name), root));
+
+        let all = group(MatchType::All);
+        assert!(matches(&all, "invoice-2024.pdf"));
+        assert!(matches(&all, "invoice-2024.docx"));
+        assert!(matches(&all, "invoice-draft.pdf"));
+        assert!(!matches(&all, "invoice-draft.docx"));
+        assert!(!matches(&all, "invoice-2024.txt"));
+        assert!(!matches(&all, "receipt.pdf"));
+        let any = group(MatchType::Any);
+        assert!(matches(&any, "notes.txt"));
+        let not = create_condition(&conditions[2]).unwrap();
+        assert!(not.evaluate(&Entry::new(&root.join("report.docx"), root)));
+        assert!(!not.evaluate(&Entry::new(&root.join("draft.docx"), root)));
+    }
+
+    #[test]
+    fn regexes_capture_groups_and_names_ignore_folders() {
+        let root = Path::new("/downloads");
+        let path = root.join("screenshot/INV-acme-2024.pdf");
+        let regex = |yaml: &str| match serde_yaml::from_str(yaml).unwrap() {
+            config::Condition::Regex { pattern, target } => RegexMatch { pattern, target },
+            _ => unreachable!(),
+        };
+
+        let entry = Entry::new(&path, root);
+        assert!(
+            regex(r"{condition_type: regex, pattern: 'INV-(?P<client>\w+)-'}").evaluate(&entry)
+        );
+        assert!(
+            regex("{condition_type: regex, pattern: '^screenshot/', target: path}")
+                .evaluate(&entry)
+        );
+        assert!(!regex("{condition_type: regex, pattern: 'pdf$', target: stem}").evaluate(&entry));
+        assert_eq!(entry.into_captures()["client"], "acme");
+
+        let name = NameContains {
+            substring: "screenshot".into(),
+        };
+        assert!(!name.evaluate(&Entry::new(&path, root)));
+    }
+
+    #[test]
+    fn globs_match_names_and_relative_paths() {
+        let root = Path::new("/downloads");
+        let glob = |patterns: &[&str], target, ignore_case| {
+            let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+            GlobMatch::new(&patterns, target, ignore_case).unwrap()
+        };
+        let matches =
+            |glob: &GlobMatch, path: &str| glob.evaluate(&Entry::new(&root.join(path), root));
+
+        let archives = glob(&["**/*.tar.gz"], Target::Path, false);
+        assert!(matches(&archives, "backup.tar.gz"));
+        assert!(matches(&archives, "old/2023/backup.tar.gz"));
+        let photos = glob(&["IMG_????.jpg", "{a,b}*.txt"], Target::Name, false);
+        assert!(matches(&photos, "trips/IMG_0042.jpg"));
+        assert!(matches(&photos, "notes/b-side.txt"));
+        assert!(!matches(&photos, "IMG_0042.JPG"));
+        assert!(!matches(&photos, "c.txt"));
+        assert!(matches(
+            &glob(&["img_*.jpg"], Target::Name, true),
+            "IMG_0042.JPG"
+        ));
+        assert!(!matches(
+            &glob(&["*.jpg"], Target::Path, false),
+            "trips/a.jpg"
+        ));
+        assert!(GlobMatch::new(&["[a-".to_string()], Target::Name, false).is_err());
+    }
+
+    fn date_match(yaml: &str) -> DateMatch {
+        DateMatch {
+            compare: serde_yaml::from_str(yaml).unwrap(),
+        }
+    }
+
+    #[test]
+    fn dates_compare_ages_and_whole_days() {
+        let at = |day, hour| -> SystemTime {
+            Local
+                .with_ymd_and_hms(2024, 1, day, hour, 0, 0)
+                .unwrap()
+                .into()
+        };
+        let now = at(31, 12);
+
+        let old = date_match("older_than: 30d");
+        assert!(old.matches(at(1, 11), now));
+        assert!(!old.matches(at(1, 13), now));
+        let recent = date_match("{time: added, within: 2h}");
+        assert!(recent.matches(at(31, 11), now));
+        assert!(!recent.matches(at(31, 9), now));
+
+        let day = date_match("is: 2024-01-15");
+        assert!(day.matches(at(15, 23), now));
+        assert!(!day.matches(at(16, 0), now));
+        let after = date_match("{after: 2024-01-15, before: 2024-01-20}");
+        assert!(!after.matches(at(15, 18), now));
+        assert!(after.matches(at(19, 23), now));
+        assert!(!after.matches(at(20, 0), now));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn sizes_compare_files_and_ranges_include_both_ends() {
+        let dir = tempdir().unwrap();
+        let sizes = [999, 1000, 1500, 2000, 2001];
+        for size in sizes.iter().copied() {
+            fs::write(dir.path().join(size.to_string()), vec![0; size]).unwrap();
+        }
+        fs::create_dir(dir.path().join("folder")).unwrap();
+        let matching = |yaml: &str| -> Vec<usize> {
+            let range = SizeRange::new(&serde_yaml::from_str(yaml).unwrap());
+            assert!(!range.evaluate(&Entry::new(&dir.path().join("folder"), dir.path())));
+            sizes
+                .iter()
+                .copied()
+                .filter(|size| {
+                    range.evaluate(&Entry::new(&dir.path().join(size.to_string()), dir.path()))
+                })
+                .collect()
+        };
+
+        assert_eq!(matching("between: [1KB, 2KB]"), [1000, 1500, 2000]);
+        assert_eq!(matching("not_between: [1KB, 2KB]"), [999, 2001]);
+        assert_eq!(matching("is_not: 1500"), [999, 1000, 2000, 2001]);
+        assert_eq!(matching("{at_least: 1KB, less_than: 2KB}"), [1000, 1500]);
+        assert_eq!(matching("{is: 2KB, at_most: 2000}"), [2000]);
+    }
+
+    #[test]
+    fn reversed_size_ranges_are_rejected_where_they_are_written() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rules.yaml");
+        fs::write(
+            &path,
+            "version: 2\nname: x\ndescription: y\nfolders:\n- path: a\n  rules:\n  - name: Big\n    conditions:\n    - condition_type: size\n      between: [10MB, 1KB]\n",
+        )
+        .unwrap();
+
+        let err = config::load_config(&path, None).unwrap_err();
+
+        assert!(
+            err.message
+                .contains("size range starts at 10000000 bytes, above its end at 1000 bytes"),
+            "{}",
+            err.message
+        );
+        // Errors inside a condition point at the condition holding them.
+        assert_eq!(err.location.map(|(line, _)| line), Some(9));
+    }
+}
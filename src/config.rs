@@ -1,181 +1,973 @@
+use crate::patterns::RegexPattern;
+use crate::units::{Age, ByteSize, Moment, SizeBounds};
+use crate::{actions, conditions, defaults, includes, migrate, paths, profiles, scan, schedule};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
 use std::fs;
-use std::io::Write;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Rule {
+    /// Rule file format version; files without one are upgraded from version 1 on load.
+    pub version: u32,
     pub name: String,
     pub description: String,
+    /// Directory that relative folder and action paths are resolved against, itself
+    /// relative to this file. Defaults to the directory the file is in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_dir: Option<String>,
+    /// User-defined values available as `$name` or `${name}` in folder and action paths.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
+    /// Other files whose folders, condition sets and snippets are merged into this one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Named condition lists, referenced with `condition_type: condition_set`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub condition_sets: BTreeMap<String, Vec<Condition>>,
+    /// Named rules that folder rules can build on with `use:`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub snippets: BTreeMap<String, FolderRule>,
+    /// Settings every folder and rule in this file starts from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub defaults: Option<Defaults>,
+    /// Named sets of changes, chosen with `--profile` or `ORDERLY_PROFILE`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
     pub folders: Vec<Folder>,
+    /// File this rule set was loaded from.
+    #[serde(skip)]
+    pub source: PathBuf,
+    /// Files pulled in through `include:`, directly or indirectly.
+    #[serde(skip)]
+    pub included: Vec<PathBuf>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Folder {
     pub path: String,
-    pub match_type: String,
+    #[serde(default)]
+    pub match_type: MatchType,
+    /// Whether files in subfolders are organized too.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub recursive: bool,
+    /// How many levels of subfolders are scanned; setting it implies `recursive`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    /// Whether files and folders whose names start with `.` are organized.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub include_hidden: bool,
+    /// Whether symlinked folders are scanned as if they were inside this folder.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub follow_symlinks: bool,
+    /// Glob patterns for files and folders to leave alone. Patterns containing a `/`
+    /// match the path relative to the folder, others match the name.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// Files whose `rules:` are added ahead of this folder's own rules.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Settings the folder's rules start from, on top of the file's `defaults`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub defaults: Option<Defaults>,
+    /// Whether the folder is organized at all. Defaults to true.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// When this folder is organized; outside the window all its rules are skipped.
+    #[serde(alias = "schedule", default, skip_serializing_if = "Option::is_none")]
+    pub active_when: Option<Schedule>,
+    /// When this folder is left alone, e.g. during working hours.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inactive_when: Option<Schedule>,
+    #[serde(default)]
     pub rules: Vec<FolderRule>,
+    /// Directory of the file this folder was included from, which its relative paths
+    /// start from.
+    #[serde(skip)]
+    pub base: Option<PathBuf>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct FolderRule {
+    #[serde(default)]
     pub name: String,
+    /// Snippet this rule builds on; fields set here take precedence over the snippet's.
+    #[serde(rename = "use", default, skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+    /// Overrides the folder's `match_type` for this rule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_type: Option<MatchType>,
+    /// Rules with a higher priority run first; equal priorities keep file order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    /// Whether a matched file is hidden from later rules. Defaults to true.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<bool>,
+    /// Whether the rule picks up `defaults`. Defaults to true.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inherit_defaults: Option<bool>,
+    /// Whether the rule runs at all. Defaults to true.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// When this rule runs; outside the window it is skipped.
+    #[serde(alias = "schedule", default, skip_serializing_if = "Option::is_none")]
+    pub active_when: Option<Schedule>,
+    /// When this rule is skipped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inactive_when: Option<Schedule>,
+    #[serde(default)]
     pub conditions: Vec<Condition>,
+    #[serde(default)]
     pub actions: Vec<Action>,
+    /// Directory of the file this rule was included from, which its relative paths
+    /// start from.
+    #[serde(skip)]
+    pub base: Option<PathBuf>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Condition {
-    pub condition_type: String,
-    pub value: String,
+/// Shared settings for the rules of a file or folder. Lists add up across levels, and
+/// a single value set closer to the rule wins.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    /// Conditions every rule must pass on top of its own.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<Condition>,
+    /// Exclude patterns added to every folder.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// What actions do when the destination exists, unless they say otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_conflict: Option<OnConflict>,
+    /// Folder pattern for `sort_by_date` actions that don't give one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort_pattern: Option<String>,
+}
+
+/// Size comparisons; every one given must hold. Ranges include both ends.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SizeComparison {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is: Option<ByteSize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_not: Option<ByteSize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub less_than: Option<ByteSize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub greater_than: Option<ByteSize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub at_most: Option<ByteSize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub at_least: Option<ByteSize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub between: Option<SizeBounds>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_between: Option<SizeBounds>,
+}
+
+/// Comparisons of one of a file's timestamps; every one given must hold. Dates are local
+/// and cover the whole day, minute or second they name, so `is: 2024-01-31` matches any
+/// time that day and `after: 2024-01-31` only times from the next day on.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DateComparison {
+    /// The timestamp compared, `modified` unless given.
+    #[serde(default)]
+    pub time: FileTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is: Option<Moment>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<Moment>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<Moment>,
+    /// Matches timestamps further back than this, such as `30d`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub older_than: Option<Age>,
+    /// Matches timestamps no further back than this, such as `2h`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub within: Option<Age>,
+}
+
+/// A file timestamp. `created` is the birth time, which not every file system records;
+/// `added` is when the file was put in its folder, which on Unix is the inode change
+/// time, since moving a file updates it.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FileTime {
+    #[default]
+    Modified,
+    Created,
+    Accessed,
+    Added,
+}
+
+/// What a `regex` or `glob` condition is matched against: the file name, the name
+/// without its extension, or the path relative to the folder, with `/` between components.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    #[default]
+    Name,
+    Stem,
+    Path,
+}
+
+/// A group of file types told apart by a `kind` condition.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FileKind {
+    Image,
+    Audio,
+    Video,
+    /// Text, PDF, office and e-book files.
+    Document,
+    /// Compressed files and archives such as zip and tar.
+    Archive,
+    /// Programs, installers and disk images.
+    Executable,
+    Font,
+}
+
+/// Changes a profile makes to its rule file when selected.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Variables added, or replacing those of the same name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
+    /// Folders added, or replacing the folder with the same `path`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub folders: Vec<Folder>,
+    /// Rule names or folder paths to turn on, for those written with `enabled: false`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub enable: Vec<String>,
+    /// Rule names or folder paths to turn off.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disable: Vec<String>,
+}
+
+/// A window of local time. Every part that is given must match: `cron` matches each
+/// minute of its pattern, `days` are weekday names or ranges such as `mon-fri`, and
+/// `from`/`to` bound the time of day as `HH:MM`, wrapping past midnight when `to` is
+/// earlier than `from`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub days: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// How a rule's conditions combine: all must pass, any one is enough, or none may pass.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchType {
+    #[default]
+    All,
+    Any,
+    None,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "condition_type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Condition {
+    Always,
+    Name {
+        value: String,
+    },
+    Extension {
+        value: Vec<String>,
+    },
+    NameContains {
+        value: String,
+    },
+    /// Passes when `pattern` matches somewhere in the file's name, stem or relative path;
+    /// anchor it with `^` and `$` to match the whole. Named groups such as `(?P<client>\w+)`
+    /// can be used as `{client}` in the paths of the rule's actions.
+    Regex {
+        pattern: RegexPattern,
+        #[serde(default)]
+        target: Target,
+    },
+    /// Passes when any of `patterns` matches the whole file name, stem or relative path.
+    /// `*` and `?` stop at `/`, while `**/` spans any number of folders.
+    Glob {
+        patterns: Vec<String>,
+        #[serde(default)]
+        target: Target,
+        #[serde(default, skip_serializing_if = "is_false")]
+        ignore_case: bool,
+    },
+    /// Compares the size of a file.
+    Size(Box<SizeComparison>),
+    /// Passes when the file's contents, or its extension when they aren't recognized,
+    /// show it to be of one of the given kinds.
+    Kind {
+        value: Vec<FileKind>,
+    },
+    /// Compares when a file was modified, created, accessed or added to its folder.
+    Date(Box<DateComparison>),
+    /// Passes when every nested condition passes.
+    All {
+        conditions: Vec<Condition>,
+    },
+    /// Passes when at least one nested condition passes.
+    Any {
+        conditions: Vec<Condition>,
+    },
+    /// Passes when none of the nested conditions pass.
+    Not {
+        conditions: Vec<Condition>,
+    },
+    /// Refers to a named entry of `condition_sets`; replaced by its conditions on load.
+    #[serde(rename = "condition_set")]
+    Set {
+        name: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "action_type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Action {
+    Move {
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        on_conflict: Option<OnConflict>,
+    },
+    Copy {
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        on_conflict: Option<OnConflict>,
+    },
+    Delete,
+    SortByDate {
+        path: String,
+        /// `strftime` pattern for the dated subfolders. Defaults to `%Y/%m`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pattern: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        on_conflict: Option<OnConflict>,
+    },
+    /// Lets later rules see the file even if the rule would otherwise stop.
+    Continue,
+}
+
+/// Pattern `sort_by_date` uses when neither the action nor `defaults` give one.
+pub const DEFAULT_SORT_PATTERN: &str = "%Y/%m";
+
+/// What an action does when a file with the same name is already at the destination.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OnConflict {
+    /// Replace the existing file.
+    #[default]
+    Overwrite,
+    /// Leave both files where they are.
+    Skip,
+    /// Add a number to the new file's name, as in `report (1).pdf`.
+    Rename,
+}
+
+/// An error tied to the rule file that caused it.
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub message: String,
+    /// Line and column of the offending YAML, when the parser knows it.
+    pub location: Option<(usize, usize)>,
+}
+
+impl ConfigError {
+    pub fn new(path: &Path, message: impl fmt::Display) -> Self {
+        ConfigError {
+            path: path.to_path_buf(),
+            message: message.to_string(),
+            location: None,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+impl Error for ConfigError {}
+
+impl Rule {
+    /// Expands variables, environment references and `~` in folder and action paths.
+    fn expand_paths(&mut self) -> Result<(), String> {
+        let Rule {
+            base_dir,
+            variables,
+            folders,
+            source,
+            ..
+        } = self;
+        let file_dir = source
+            .canonicalize()
+            .unwrap_or_else(|_| source.clone())
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let base = match base_dir {
+            Some(dir) => {
+                let expanded =
+                    paths::expand(dir, variables).map_err(|e| format!("base_dir: {}", e))?;
+                let dir = file_dir.join(expanded);
+                dir.canonicalize().unwrap_or(dir)
+            }
+            None => file_dir,
+        };
+        for folder in folders.iter_mut() {
+            let folder_path = folder.path.clone();
+            let expanded = paths::expand(&folder_path, variables)
+                .map_err(|e| format!("folder `{}`: {}", folder_path, e))?;
+            let folder_base = folder.base.as_ref().unwrap_or(&base);
+            folder.path = paths::resolve(&expanded, folder_base);
+            for rule in folder.rules.iter_mut() {
+                let rule_base = rule.base.as_ref().unwrap_or(folder_base);
+                let rule_name = &rule.name;
+                for action in rule.actions.iter_mut() {
+                    if let Some(path) = action.path_mut() {
+                        let expanded = paths::expand(path, variables).map_err(|e| {
+                            format!("folder `{}`, rule `{}`: {}", folder_path, rule_name, e)
+                        })?;
+                        *path = paths::resolve(&expanded, rule_base);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Folder {
+    /// The folder's rules in the order they run.
+    pub fn rules_by_priority(&self) -> Vec<&FolderRule> {
+        let mut rules: Vec<_> = self.rules.iter().collect();
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority.unwrap_or(0)));
+        rules
+    }
+}
+
+impl FolderRule {
+    /// Whether files this rule matches are hidden from the rules after it.
+    pub fn stops(&self) -> bool {
+        self.stop.unwrap_or(true) && !self.actions.iter().any(|a| matches!(a, Action::Continue))
+    }
+
+    /// Whether the rule only runs at some times.
+    pub fn is_scheduled(&self) -> bool {
+        self.active_when.is_some() || self.inactive_when.is_some()
+    }
+}
+
+impl Action {
+    /// The destination path of the action, if it has one.
+    pub fn path_mut(&mut self) -> Option<&mut String> {
+        match self {
+            Action::Move { path, .. }
+            | Action::Copy { path, .. }
+            | Action::SortByDate { path, .. } => Some(path),
+            Action::Delete | Action::Continue => None,
+        }
+    }
+}
+
+/// Rule file formats, chosen by file extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => Some(Format::Yaml),
+            Some("json") => Some(Format::Json),
+            Some("toml") => Some(Format::Toml),
+            _ => None,
+        }
+    }
+
+    /// The format of a rule file; files with other extensions are read as YAML.
+    pub fn of(path: &Path) -> Format {
+        Format::from_path(path).unwrap_or(Format::Yaml)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Action {
-    pub action_type: String,
-    pub path: Option<String>,
-    pub pattern: Option<String>, // Added pattern field
+/// Reads and deserializes a rule file without resolving anything in it.
+///
+/// Files written for an older version of the format are upgraded in memory first.
+pub fn parse_file<T: DeserializeOwned>(path: &Path) -> Result<T, ConfigError> {
+    let content = fs::read_to_string(path).map_err(|e| ConfigError::new(path, e))?;
+    let mut document: serde_json::Value = parse_content(path, &content)?;
+    let version = migrate::version_of(&document).map_err(|e| ConfigError::new(path, e))?;
+    if version == migrate::CURRENT_VERSION {
+        // Parse the text again so errors keep their line and column.
+        return parse_content(path, &content);
+    }
+    migrate::upgrade(&mut document, version);
+    if let Some((text, origins)) = migrate::upgraded_text(path, &content, version, &document) {
+        // Reading the upgraded text keeps line and column in errors.
+        return parse_content(path, &text).map_err(|e| original_line(e, &origins));
+    }
+    serde_json::from_value(document).map_err(|e| {
+        let message = format!("{} (after upgrading from version {})", e, version);
+        ConfigError::new(path, message)
+    })
 }
 
-pub fn load_config(path: &str) -> Result<Rule, Box<dyn std::error::Error>> {
-    let content = fs::read_to_string(path)?;
-    let rule: Rule = serde_yaml::from_str(&content)?;
+/// Deserializes rule file text in the format matching `path`.
+pub fn parse_content<T: DeserializeOwned>(path: &Path, content: &str) -> Result<T, ConfigError> {
+    match Format::of(path) {
+        Format::Yaml => serde_yaml::from_str(content).map_err(|e| ConfigError {
+            location: e.location().map(|l| (l.line(), l.column())),
+            ..ConfigError::new(path, &e)
+        }),
+        Format::Json => serde_json::from_str(content).map_err(|e| ConfigError {
+            location: Some((e.line(), e.column())).filter(|&(line, _)| line > 0),
+            ..ConfigError::new(path, &e)
+        }),
+        Format::Toml => toml::from_str(content).map_err(|e| {
+            let location = e.span().map(|span| line_and_column(content, span.start));
+            let message = match location {
+                Some((line, column)) => {
+                    format!("{} at line {} column {}", e.message(), line, column)
+                }
+                None => e.message().to_string(),
+            };
+            ConfigError {
+                location,
+                ..ConfigError::new(path, message.trim_end())
+            }
+        }),
+    }
+}
+
+/// Points an error in upgraded text back at the line of the original file it came from.
+fn original_line(mut e: ConfigError, origins: &[usize]) -> ConfigError {
+    if let Some((l, column)) = e.location {
+        let original = origins.get(l - 1).copied().unwrap_or(l);
+        let from = format!("line {} column {}", l, column);
+        e.message = e
+            .message
+            .replace(&from, &format!("line {} column {}", original, column));
+        e.location = Some((original, column));
+    }
+    e
+}
+
+/// 1-based line and column of a byte offset.
+fn line_and_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+/// Serializes a rule file's contents in the given format.
+pub fn serialize<T: Serialize>(format: Format, value: &T) -> io::Result<String> {
+    Ok(match format {
+        Format::Yaml => serde_yaml::to_string(value).map_err(io::Error::other)?,
+        Format::Json => serde_json::to_string_pretty(value).map_err(io::Error::other)? + "\n",
+        Format::Toml => toml::to_string_pretty(value).map_err(io::Error::other)?,
+    })
+}
+
+/// Serializes a rule set in the format matching `dest` and writes it there.
+pub fn write_rule_file(dest: &Path, rule: &Rule) -> io::Result<()> {
+    let content = serialize(Format::of(dest), rule)?;
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = fs::File::create(dest)?;
+    file.write_all(content.as_bytes())
+}
+
+/// Loads a rule set, merging in `profile` if the file defines it.
+pub fn load_config(path: &Path, profile: Option<&str>) -> Result<Rule, ConfigError> {
+    let mut rule: Rule = parse_file(path)?;
+    rule.source = path.to_path_buf();
+    if let Some(profile) = profile {
+        profiles::merge(&mut rule, profile);
+    }
+    includes::resolve(&mut rule)?;
+    profiles::prune(&mut rule, profile).map_err(|e| ConfigError::new(path, e))?;
+    defaults::apply(&mut rule);
+    rule.expand_paths().map_err(|e| ConfigError::new(path, e))?;
+    for folder in &rule.folders {
+        let folder_error = |e| ConfigError::new(path, format!("folder `{}`: {}", folder.path, e));
+        scan::Scanner::new(folder).map_err(folder_error)?;
+        schedule::validate(&folder.active_when, &folder.inactive_when).map_err(folder_error)?;
+        for folder_rule in &folder.rules {
+            let rule_error =
+                |e| ConfigError::new(path, format!("rule `{}`: {}", folder_rule.name, e));
+            schedule::validate(&folder_rule.active_when, &folder_rule.inactive_when)
+                .map_err(rule_error)?;
+            conditions::create_group(&folder_rule.conditions, MatchType::All)
+                .map_err(rule_error)?;
+            for action in &folder_rule.actions {
+                if let Action::SortByDate {
+                    pattern: Some(pattern),
+                    ..
+                } = action
+                {
+                    actions::validate_date_pattern(pattern)
+                        .map_err(|e| rule_error(e.to_string()))?;
+                }
+            }
+        }
+    }
     Ok(rule)
 }
 
-pub fn create_example_rule() -> std::io::Result<()> {
-    let example_rule = Rule {
-        name: "Orderly Sandbox Rules".into(),
-        description: "Rules for organizing files in the sandbox environment".into(),
-        folders: vec![
-            Folder {
-                path: "test_folder/Desktop".into(),
-                match_type: "all".into(),
-                rules: vec![FolderRule {
-                    name: "Move all files to Downloads".into(),
-                    conditions: vec![Condition {
-                        condition_type: "always".into(),
-                        value: "".into(),
-                    }],
-                    actions: vec![Action {
-                        action_type: "move".into(),
-                        path: Some("test_folder/Downloads".into()),
-                        pattern: None,
-                    }],
-                }],
-            },
-            Folder {
-                path: "test_folder/Downloads".into(),
-                match_type: "all".into(),
-                rules: vec![
-                    FolderRule {
-                        name: "Move music files to Music folder".into(),
-                        conditions: vec![Condition {
-                            condition_type: "extension".into(),
-                            value: "mp3,flac,wav,ogg,m4a,wma,aac,aiff,aif".into(),
-                        }],
-                        actions: vec![Action {
-                            action_type: "move".into(),
-                            path: Some("test_folder/Music".into()),
-                            pattern: None,
-                        }],
-                    },
-                    FolderRule {
-                        name: "Move video files to Videos folder".into(),
-                        conditions: vec![Condition {
-                            condition_type: "extension".into(),
-                            value: "mp4,mov,avi,wmv,mkv".into(),
-                        }],
-                        actions: vec![Action {
-                            action_type: "move".into(),
-                            path: Some("test_folder/Videos".into()),
-                            pattern: None,
-                        }],
-                    },
-                    FolderRule {
-                        name: "Move document files to Documents folder".into(),
-                        conditions: vec![Condition {
-                            condition_type: "extension".into(),
-                            value: "pdf,txt,doc,docx,xls,xlsx,ppt,pptx".into(),
-                        }],
-                        actions: vec![Action {
-                            action_type: "move".into(),
-                            path: Some("test_folder/Documents".into()),
-                            pattern: None,
-                        }],
-                    },
-                    FolderRule {
-                        name: "Move picture files to Pictures folder".into(),
-                        conditions: vec![Condition {
-                            condition_type: "extension".into(),
-                            value: "jpg,png,gif".into(),
-                        }],
-                        actions: vec![Action {
-                            action_type: "move".into(),
-                            path: Some("test_folder/Pictures".into()),
-                            pattern: None,
-                        }],
-                    },
-                ],
-            },
-            Folder {
-                path: "test_folder/Pictures".into(),
-                match_type: "all".into(),
-                rules: vec![
-                    FolderRule {
-                        name: "Move wallpapers to Wallpapers subfolder".into(),
-                        conditions: vec![Condition {
-                            condition_type: "name_contains".into(),
-                            value: "wallpaper".into(),
-                        }],
-                        actions: vec![Action {
-                            action_type: "move".into(),
-                            path: Some("test_folder/Pictures/Wallpapers".into()),
-                            pattern: None,
-                        }],
-                    },
-                    FolderRule {
-                        name: "Move clearshots to Wallpapers subfolder".into(),
-                        conditions: vec![Condition {
-                            condition_type: "name_contains".into(),
-                            value: "clearshot".into(),
-                        }],
-                        actions: vec![Action {
-                            action_type: "move".into(),
-                            path: Some("test_folder/Pictures/Wallpapers".into()),
-                            pattern: None,
-                        }],
-                    },
-                    FolderRule {
-                        name: "Move screenshots to Screenshots subfolder".into(),
-                        conditions: vec![Condition {
-                            condition_type: "name_contains".into(),
-                            value: "screenshot".into(),
-                        }],
-                        actions: vec![Action {
-                            action_type: "move".into(),
-                            path: Some("test_folder/Pictures/Screenshots".into()),
-                            pattern: None,
-                        }],
-                    },
-                    FolderRule {
-                        name: "Sort images into year/month subfolders".into(),
-                        conditions: vec![Condition {
-                            condition_type: "extension".into(),
-                            value: "jpg,png,gif".into(),
-                        }],
-                        actions: vec![Action {
-                            action_type: "sort_by_date".into(),
-                            path: Some("test_folder/Pictures".into()),
-                            pattern: Some("%Y/%b".into()),
-                        }],
-                    },
-                ],
-            },
-        ],
+/// Loads a single rule file, or every rule file found under a directory.
+pub fn load_rules(path: &Path, profile: Option<&str>) -> Result<Vec<Rule>, Box<dyn Error>> {
+    let (rules, mut errors) = load_all(path, profile);
+    if errors.is_empty() {
+        Ok(rules)
+    } else {
+        Err(errors.remove(0).into())
+    }
+}
+
+/// Loads every rule set under `path`, collecting errors instead of stopping at the first.
+///
+/// Files pulled in by another file's `include:` are fragments, not rule sets of their
+/// own, so they are left out.
+pub fn load_all(path: &Path, profile: Option<&str>) -> (Vec<Rule>, Vec<ConfigError>) {
+    let files = match rule_files(path) {
+        Ok(files) => files,
+        Err(e) => return (Vec::new(), vec![e]),
     };
+    let canonical: Vec<PathBuf> = files
+        .iter()
+        .map(|file| file.canonicalize().unwrap_or_else(|_| file.clone()))
+        .collect();
+    let declared: Vec<Vec<PathBuf>> = files
+        .iter()
+        .map(|file| includes::declared_includes(file))
+        .collect();
+
+    let mut rules = Vec::new();
+    let mut errors = Vec::new();
+    for (i, file) in files.iter().enumerate() {
+        // Files in an include cycle are still loaded, so the cycle gets reported.
+        let in_cycle = declared[i].contains(&canonical[i]);
+        if !in_cycle && declared.iter().flatten().any(|p| *p == canonical[i]) {
+            continue;
+        }
+        match load_config(file, profile) {
+            Ok(rule) => rules.push(rule),
+            Err(e) => errors.push(e),
+        }
+    }
+    if let Some(profile) = profile {
+        if errors.is_empty() && !rules.iter().any(|r| r.profiles.contains_key(profile)) {
+            errors.push(ConfigError::new(
+                path,
+                format!("no rule file defines profile `{}`", profile),
+            ));
+        }
+    }
+    (rules, errors)
+}
+
+/// Lists the rule files that `path` refers to: the file itself, or every rule file under a directory.
+pub fn rule_files(path: &Path) -> Result<Vec<PathBuf>, ConfigError> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let files = find_rule_files(path).map_err(|e| ConfigError::new(path, e))?;
+    if files.is_empty() {
+        return Err(ConfigError::new(path, "no rule files found"));
+    }
+    Ok(files)
+}
+
+pub fn is_rule_file(path: &Path) -> bool {
+    Format::from_path(path).is_some()
+}
+
+/// Recursively collects rule files under `dir`, sorted so the load order is stable.
+fn find_rule_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(find_rule_files(&path)?);
+        } else if is_rule_file(&path) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const MINIMAL: &str = "name: Minimal\ndescription: test\nfolders: []\n";
+
+    #[test]
+    fn load_rules_reads_every_rule_file_in_a_directory() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("b.yaml"), MINIMAL.replace("Minimal", "B")).unwrap();
+        fs::create_dir(dir.path().join("nested")).unwrap();
+        fs::write(dir.path().join("nested/a.yml"), MINIMAL).unwrap();
+        fs::write(dir.path().join("notes.txt"), "not a rule file").unwrap();
+
+        let rules = load_rules(dir.path(), None).unwrap();
+
+        let names: Vec<_> = rules.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["B", "Minimal"]);
+        assert_eq!(rules[1].source, dir.path().join("nested/a.yml"));
+    }
+
+    #[test]
+    fn config_path_can_be_a_single_file_and_errors_are_collected_per_file() {
+        let dir = tempdir().unwrap();
+        let good = dir.path().join("good.yaml");
+        fs::write(&good, MINIMAL).unwrap();
+        fs::write(dir.path().join("bad.json"), "{").unwrap();
+        fs::write(dir.path().join("worse.toml"), "name = ").unwrap();
+
+        let single = load_rules(&good, None).unwrap();
+        let (rules, errors) = load_all(dir.path(), None);
+        let empty = tempdir().unwrap();
+
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].source, good);
+        assert_eq!(rules.len(), 1);
+        let failed: Vec<_> = errors.iter().map(|e| e.path.file_name().unwrap()).collect();
+        assert_eq!(failed, ["bad.json", "worse.toml"]);
+        let err = load_rules(empty.path(), None).unwrap_err();
+        assert!(err.to_string().ends_with("no rule files found"), "{}", err);
+    }
+
+    #[test]
+    fn yaml_json_and_toml_rule_files_load_the_same_rules() {
+        let dir = tempdir().unwrap();
+        let files = [
+            ("rules.yaml", "version: 2\nname: Docs\ndescription: d\nfolders:\n- path: in\n  match_type: any\n  rules:\n  - name: pdf\n    conditions:\n    - {condition_type: extension, value: [pdf]}\n    - {condition_type: size, less_than: 1 MB}\n    actions:\n    - {action_type: move, path: out, on_conflict: rename}\n"),
+            ("rules.json", r#"{"version": 2, "name": "Docs", "description": "d", "folders": [{"path": "in", "match_type": "any", "rules": [{"name": "pdf",
+                "conditions": [{"condition_type": "extension", "value": ["pdf"]}, {"condition_type": "size", "less_than": "1 MB"}],
+                "actions": [{"action_type": "move", "path": "out", "on_conflict": "rename"}]}]}]}"#),
+            ("rules.toml", "version = 2\nname = \"Docs\"\ndescription = \"d\"\n[[folders]]\npath = \"in\"\nmatch_type = \"any\"\n[[folders.rules]]\nname = \"pdf\"\nconditions = [{ condition_type = \"extension\", value = [\"pdf\"] }, { condition_type = \"size\", less_than = \"1 MB\" }]\nactions = [{ action_type = \"move\", path = \"out\", on_conflict = \"rename\" }]\n"),
+        ];
+        for (name, content) in &files {
+            fs::write(dir.path().join(name), content).unwrap();
+        }
+
+        let loaded: Vec<_> = files
+            .iter()
+            .map(|(name, _)| {
+                let rule = load_config(&dir.path().join(name), None).unwrap();
+                serde_json::to_value(&rule).unwrap()
+            })
+            .collect();
+
+        assert_eq!(loaded[0], loaded[1]);
+        assert_eq!(loaded[0], loaded[2]);
+        assert_eq!(
+            loaded[0]["folders"][0]["rules"][0]["conditions"][1]["less_than"],
+            "1 MB"
+        );
+        for format in [Format::Yaml, Format::Json, Format::Toml] {
+            let rule: Rule = serde_json::from_value(loaded[0].clone()).unwrap();
+            let text = serialize(format, &rule).unwrap();
+            let path = dir
+                .path()
+                .join(format!("again.{:?}", format).to_lowercase());
+            assert_eq!(Format::of(&path), format);
+            let again: Rule = parse_content(&path, &text).unwrap();
+            assert_eq!(serde_json::to_value(&again).unwrap(), loaded[0]);
+        }
+    }
+
+    #[test]
+    fn schema_describes_every_condition_and_action_type() {
+        let schema = serde_json::to_string(&schemars::schema_for!(Rule)).unwrap();
+
+        for name in [
+            "extension",
+            "regex",
+            "glob",
+            "size",
+            "date",
+            "kind",
+            "not",
+            "sort_by_date",
+            "continue",
+        ] {
+            assert!(
+                schema.contains(&format!("\"{}\"", name)),
+                "missing {}",
+                name
+            );
+        }
+        assert!(schema.contains("\"additionalProperties\":false"));
+    }
+
+    #[test]
+    fn load_config_errors_name_the_file_and_location() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("broken.yaml");
+        fs::write(&path, "name: x\ndescription: y\nfolders:\n- path: a\n  rules:\n  - name: r\n    conditions: []\n    actions:\n    - action_type: move\n").unwrap();
+
+        let err = load_config(&path, None).unwrap_err();
+
+        assert_eq!(err.path, path);
+        assert!(err.message.contains("missing field `path`"));
+        assert_eq!(err.location.map(|(line, _)| line), Some(9));
+    }
+
+    /// The start of the example rule file `--init` wrote before rule files were versioned.
+    const BASELINE_EXAMPLE: &str = "name: Orderly Sandbox Rules
+description: Rules for organizing files in the sandbox environment
+folders:
+- path: test_folder/Desktop
+  match_type: all
+  rules:
+  - name: Move all files to Downloads
+    conditions:
+    - condition_type: always
+      value: ''
+    actions:
+    - action_type: move
+      path: test_folder/Downloads
+      pattern: null
+- path: test_folder/Pictures
+  match_type: all
+  rules:
+  - name: Sort images into year/month subfolders
+    conditions:
+    - condition_type: extension
+      value: jpg,png,gif
+    actions:
+    - action_type: sort_by_date
+      path: test_folder/Pictures
+      pattern: '%Y/%b'
+";
+
+    #[test]
+    fn rule_files_written_by_the_original_init_still_load() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("example.yaml");
+        fs::write(&path, BASELINE_EXAMPLE).unwrap();
+        let broken = dir.path().join("broken.yaml");
+        fs::write(
+            &broken,
+            BASELINE_EXAMPLE.replace("action_type: sort_by_date", "action_type: sort"),
+        )
+        .unwrap();
+
+        let rule = load_config(&path, None).unwrap();
+        let err = load_config(&broken, None).unwrap_err();
+
+        assert_eq!(rule.version, migrate::CURRENT_VERSION);
+        assert!(matches!(
+            &rule.folders[0].rules[0].actions[0],
+            Action::Move { .. }
+        ));
+        assert!(matches!(
+            &rule.folders[1].rules[0].actions[0],
+            Action::SortByDate { pattern: Some(pattern), .. } if pattern == "%Y/%b"
+        ));
+        assert_eq!(err.location.map(|(line, _)| line), Some(23));
+    }
+
+    #[test]
+    fn misspelled_keys_and_unknown_types_are_rejected() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("typos.yaml");
+        let load = |body: &str| {
+            let rule = format!(
+                "name: x\ndescription: y\nfolders:\n- path: a\n  rules:\n  - name: r\n{}",
+                body
+            );
+            fs::write(&path, rule).unwrap();
+            load_config(&path, None).map(|_| ()).map_err(|e| e.message)
+        };
+
+        let match_type = load("    match_typ: any\n").unwrap_err();
+        let pattern =
+            load("    conditions:\n    - condition_type: regex\n      pattren: x\n").unwrap_err();
+        let size = load("    conditions:\n    - condition_type: size\n      les_than: 1 KB\n")
+            .unwrap_err();
+        let kind = load("    conditions:\n    - condition_type: colour\n").unwrap_err();
+
+        assert!(
+            match_type.contains("unknown field `match_typ`"),
+            "{}",
+            match_type
+        );
+        assert!(pattern.contains("unknown field `pattren`"), "{}", pattern);
+        assert!(size.contains("unknown field `les_than`"), "{}", size);
+        assert!(kind.contains("unknown variant `colour`"), "{}", kind);
+        load("    conditions:\n    - condition_type: size\n      less_than: 1 KB\n").unwrap();
+    }
+
+    #[test]
+    fn variables_environment_and_home_expand_in_paths() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("shared.yaml");
+        fs::write(&path, "name: x\ndescription: y\nvariables:\n  archive: ~/Archive\n  invoices: ${archive}/Invoices\nfolders:\n- path: $HOME/Downloads\n  rules:\n  - name: r\n    actions:\n    - action_type: move\n      path: $invoices/$$cash\n").unwrap();
+
+        let rule = load_config(&path, None).unwrap();
+
+        let home = paths::home_dir().unwrap();
+        assert_eq!(Path::new(&rule.folders[0].path), home.join("Downloads"));
+        assert!(matches!(
+            &rule.folders[0].rules[0].actions[0],
+            Action::Move { path, .. } if Path::new(path) == home.join("Archive/Invoices/$cash")
+        ));
+    }
+
+    #[test]
+    fn relative_paths_start_from_the_rule_file() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir(root.join("rules")).unwrap();
+        let path = root.join("rules/sandbox.yaml");
+        let rules = "name: x\ndescription: y\nfolders:\n- path: inbox\n  rules:\n  - name: r\n    conditions: []\n    actions:\n    - action_type: move\n      path: /done\n";
+        fs::write(&path, rules).unwrap();
+        let with_base_dir = root.join("rules/with_base_dir.yaml");
+        fs::write(&with_base_dir, format!("base_dir: ..\n{}", rules)).unwrap();
+
+        let rule = load_config(&path, None).unwrap();
+        let rebased = load_config(&with_base_dir, None).unwrap();
 
-    let yaml = serde_yaml::to_string(&example_rule).unwrap();
-    let mut file = fs::File::create("rules/example.yaml")?;
-    file.write_all(yaml.as_bytes())?;
-    Ok(())
+        assert_eq!(Path::new(&rule.folders[0].path), root.join("rules/inbox"));
+        assert_eq!(Path::new(&rebased.folders[0].path), root.join("inbox"));
+        assert!(matches!(
+            &rebased.folders[0].rules[0].actions[0],
+            Action::Move { path, .. } if path == "/done"
+        ));
+    }
 }
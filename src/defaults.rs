@@ -0,0 +1,131 @@
+use crate::config::{Action, Condition, Defaults, MatchType, Rule};
+use std::mem;
+
+/// Folds the file's and each folder's `defaults:` into the folders and rules they
+/// cover, so the engine only ever sees plain rules.
+pub fn apply(rule: &mut Rule) {
+    for folder in &mut rule.folders {
+        let defaults = merge(rule.defaults.as_ref(), folder.defaults.as_ref());
+        folder.exclude.extend(defaults.exclude.iter().cloned());
+        for folder_rule in &mut folder.rules {
+            if folder_rule.inherit_defaults == Some(false) {
+                continue;
+            }
+            if !defaults.conditions.is_empty() {
+                // The defaults must pass whatever the rule's own match type is.
+                let own = mem::take(&mut folder_rule.conditions);
+                let own = match folder_rule.match_type.unwrap_or(folder.match_type) {
+                    MatchType::All => own,
+                    MatchType::Any => vec![Condition::Any { conditions: own }],
+                    MatchType::None => vec![Condition::Not { conditions: own }],
+                };
+                folder_rule.conditions = defaults.conditions.iter().cloned().chain(own).collect();
+                folder_rule.match_type = Some(MatchType::All);
+            }
+            for action in &mut folder_rule.actions {
+                inherit(action, &defaults);
+            }
+        }
+    }
+}
+
+/// The folder's defaults on top of the file's.
+fn merge(file: Option<&Defaults>, folder: Option<&Defaults>) -> Defaults {
+    let mut merged = file.cloned().unwrap_or_default();
+    if let Some(folder) = folder {
+        merged.conditions.extend(folder.conditions.iter().cloned());
+        merged.exclude.extend(folder.exclude.iter().cloned());
+        merged.on_conflict = folder.on_conflict.or(merged.on_conflict);
+        merged.sort_pattern = folder.sort_pattern.clone().or(merged.sort_pattern);
+    }
+    merged
+}
+
+fn inherit(action: &mut Action, defaults: &Defaults) {
+    match action {
+        Action::Move { on_conflict, .. } | Action::Copy { on_conflict, .. } => {
+            *on_conflict = on_conflict.or(defaults.on_conflict);
+        }
+        Action::SortByDate {
+            pattern,
+            on_conflict,
+            ..
+        } => {
+            if pattern.is_none() {
+                pattern.clone_from(&defaults.sort_pattern);
+            }
+            *on_conflict = on_conflict.or(defaults.on_conflict);
+        }
+        Action::Delete | Action::Continue => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{self, Action, Condition, MatchType, OnConflict};
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn rules_inherit_file_and_folder_defaults() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rules.yaml");
+        fs::write(
+            &path,
+            r#"version: 2
+name: x
+description: y
+defaults:
+  on_conflict: rename
+  exclude: ["*.part"]
+  conditions:
+  - condition_type: name_contains
+    value: invoice
+folders:
+- path: in
+  match_type: any
+  defaults:
+    on_conflict: skip
+    sort_pattern: "%Y"
+  rules:
+  - name: Sort
+    conditions:
+    - condition_type: extension
+      value: [pdf]
+    actions:
+    - action_type: sort_by_date
+      path: out
+  - name: Own
+    inherit_defaults: false
+    conditions: []
+    actions:
+    - action_type: move
+      path: out
+"#,
+        )
+        .unwrap();
+
+        let rule_set = config::load_config(&path, None).unwrap();
+
+        let folder = &rule_set.folders[0];
+        assert_eq!(folder.exclude, ["*.part"]);
+        let sort = &folder.rules[0];
+        assert_eq!(sort.match_type, Some(MatchType::All));
+        assert!(matches!(
+            &sort.conditions[..],
+            [Condition::NameContains { .. }, Condition::Any { .. }]
+        ));
+        assert!(matches!(
+            &sort.actions[0],
+            Action::SortByDate { pattern: Some(pattern), on_conflict: Some(OnConflict::Skip), .. }
+                if pattern == "%Y"
+        ));
+        assert!(matches!(
+            &folder.rules[1].actions[0],
+            Action::Move {
+                on_conflict: None,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,379 @@
+use crate::config::{Action, Rule, DEFAULT_SORT_PATTERN};
+use crate::scan::Scanner;
+use std::fmt::Write;
+use std::path::{Component, Path, PathBuf};
+
+/// Output formats for `orderly graph`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Dot,
+    Mermaid,
+}
+
+/// Where files flow: watched folders and action destinations, joined by the actions
+/// that send files from one to the other.
+pub struct Graph {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+struct Node {
+    label: String,
+    watched: bool,
+    trash: bool,
+}
+
+struct Edge {
+    from: usize,
+    to: usize,
+    label: String,
+    copy: bool,
+    /// The destination lies inside a recursively scanned folder, so files there are
+    /// picked up by that folder's rules without any action sending them.
+    scan: bool,
+    in_cycle: bool,
+}
+
+impl Graph {
+    pub fn build(rule_sets: &[Rule]) -> Graph {
+        let mut graph = Graph {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+        };
+        let folders = rule_sets.iter().flat_map(|rule_set| &rule_set.folders);
+        for folder in folders.clone() {
+            let index = graph.node(&folder.path);
+            graph.nodes[index].watched = true;
+        }
+        for folder in folders {
+            let from = graph.node(&folder.path);
+            for rule in folder.rules_by_priority() {
+                for action in &rule.actions {
+                    let (to, copy) = match action {
+                        Action::Move { path, .. } => (graph.node(path), false),
+                        Action::Copy { path, .. } => (graph.node(path), true),
+                        Action::SortByDate { path, pattern, .. } => {
+                            let pattern = pattern.as_deref().unwrap_or(DEFAULT_SORT_PATTERN);
+                            (graph.node(&date_base(path, pattern)), false)
+                        }
+                        Action::Delete => (graph.trash(), false),
+                        Action::Continue => continue,
+                    };
+                    graph.edges.push(Edge {
+                        from,
+                        to,
+                        label: rule.name.clone(),
+                        copy,
+                        scan: false,
+                        in_cycle: false,
+                    });
+                }
+            }
+        }
+        graph.link_scanned(rule_sets);
+        for i in 0..graph.edges.len() {
+            let (from, to) = (graph.edges[i].from, graph.edges[i].to);
+            graph.edges[i].in_cycle = graph.reaches(to, from);
+        }
+        graph
+    }
+
+    /// Joins each node to the innermost other folder whose scan reaches files in it.
+    fn link_scanned(&mut self, rule_sets: &[Rule]) {
+        let scanners: Vec<_> = rule_sets
+            .iter()
+            .flat_map(|rule_set| &rule_set.folders)
+            .filter_map(|folder| Some((normalize(&folder.path), Scanner::new(folder).ok()?)))
+            .collect();
+        for from in 0..self.nodes.len() {
+            let node = &self.nodes[from];
+            if node.trash {
+                continue;
+            }
+            let file = Path::new(&node.label).join("file");
+            let folder = scanners
+                .iter()
+                .filter(|(path, scanner)| *path != node.label && scanner.covers(&file))
+                .max_by_key(|(path, _)| path.len());
+            if let Some((path, _)) = folder {
+                let to = self.node(path);
+                self.nodes[from].watched = true;
+                self.edges.push(Edge {
+                    from,
+                    to,
+                    label: "recursive scan".into(),
+                    copy: false,
+                    scan: true,
+                    in_cycle: false,
+                });
+            }
+        }
+    }
+
+    fn node(&mut self, path: &str) -> usize {
+        let label = normalize(path);
+        if let Some(index) = self.nodes.iter().position(|n| !n.trash && n.label == label) {
+            return index;
+        }
+        self.nodes.push(Node {
+            label,
+            watched: false,
+            trash: false,
+        });
+        self.nodes.len() - 1
+    }
+
+    fn trash(&mut self) -> usize {
+        if let Some(index) = self.nodes.iter().position(|n| n.trash) {
+            return index;
+        }
+        self.nodes.push(Node {
+            label: "Trash".into(),
+            watched: false,
+            trash: true,
+        });
+        self.nodes.len() - 1
+    }
+
+    /// Whether `to` can be reached from `from` by following edges.
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        let mut seen = vec![false; self.nodes.len()];
+        let mut stack = vec![from];
+        while let Some(node) = stack.pop() {
+            if node == to {
+                return true;
+            }
+            if std::mem::replace(&mut seen[node], true) {
+                continue;
+            }
+            stack.extend(self.edges.iter().filter(|e| e.from == node).map(|e| e.to));
+        }
+        false
+    }
+
+    fn node_in_cycle(&self, index: usize) -> bool {
+        self.edges
+            .iter()
+            .any(|e| e.in_cycle && (e.from == index || e.to == index))
+    }
+
+    /// A destination nobody watches, where files end up and stay.
+    fn is_sink(&self, node: &Node) -> bool {
+        !node.watched && !node.trash
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Dot => self.to_dot(),
+            Format::Mermaid => self.to_mermaid(),
+        }
+    }
+
+    fn to_dot(&self) -> String {
+        let mut out = String::from("digraph orderly {\n    rankdir=LR;\n    node [shape=box];\n");
+        for (i, node) in self.nodes.iter().enumerate() {
+            let mut attrs = vec![format!("label=\"{}\"", dot_escape(&node.label))];
+            if node.trash {
+                attrs.push("shape=ellipse".into());
+            } else if self.is_sink(node) {
+                attrs.push("style=dashed".into());
+                attrs.push("color=darkorange".into());
+                attrs.push("xlabel=\"unwatched\"".into());
+            }
+            if self.node_in_cycle(i) {
+                attrs.push("color=red".into());
+            }
+            writeln!(out, "    n{} [{}];", i, attrs.join(", ")).unwrap();
+        }
+        for edge in &self.edges {
+            let mut attrs = vec![format!("label=\"{}\"", dot_escape(&edge.label))];
+            if edge.copy {
+                attrs.push("style=dashed".into());
+            } else if edge.scan {
+                attrs.push("style=dotted".into());
+            }
+            if edge.in_cycle {
+                attrs.push("color=red".into());
+                attrs.push("penwidth=2".into());
+            }
+            writeln!(
+                out,
+                "    n{} -> n{} [{}];",
+                edge.from,
+                edge.to,
+                attrs.join(", ")
+            )
+            .unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn to_mermaid(&self) -> String {
+        let mut out = String::from("flowchart LR\n");
+        for (i, node) in self.nodes.iter().enumerate() {
+            let label = mermaid_escape(&node.label);
+            let shape = if node.trash {
+                format!("(\"{}\")", label)
+            } else {
+                format!("[\"{}\"]", label)
+            };
+            let class = if self.node_in_cycle(i) {
+                ":::cycle"
+            } else if self.is_sink(node) {
+                ":::sink"
+            } else {
+                ""
+            };
+            writeln!(out, "    n{}{}{}", i, shape, class).unwrap();
+        }
+        for edge in &self.edges {
+            let arrow = if edge.copy || edge.scan {
+                "-.->"
+            } else {
+                "-->"
+            };
+            writeln!(
+                out,
+                "    n{} {}|\"{}\"| n{}",
+                edge.from,
+                arrow,
+                mermaid_escape(&edge.label),
+                edge.to
+            )
+            .unwrap();
+        }
+        for (i, edge) in self.edges.iter().enumerate() {
+            if edge.in_cycle {
+                writeln!(out, "    linkStyle {} stroke:red,stroke-width:2px", i).unwrap();
+            }
+        }
+        out.push_str("    classDef sink stroke:darkorange,stroke-dasharray:5 5\n");
+        out.push_str("    classDef cycle stroke:red,stroke-width:2px\n");
+        out
+    }
+}
+
+/// Compares paths by their components, so `a/b/`, `a/./b` and `a/x/../b` are the same
+/// folder. `..` is collapsed without looking at the disk.
+fn normalize(path: &str) -> String {
+    let mut normal = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normal.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normal.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => normal.push(".."),
+            },
+            _ => normal.push(component),
+        }
+    }
+    normal.to_string_lossy().into_owned()
+}
+
+/// The folder `sort_by_date` sends files into: `path` plus the leading part of the
+/// pattern that doesn't depend on the date.
+fn date_base(path: &str, pattern: &str) -> String {
+    let fixed: PathBuf = Path::new(pattern)
+        .components()
+        .take_while(|c| !c.as_os_str().to_string_lossy().contains('%'))
+        .collect();
+    Path::new(path).join(fixed).to_string_lossy().into_owned()
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn mermaid_escape(text: &str) -> String {
+    text.replace('"', "#quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn highlights_cycles_and_unwatched_sinks() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rules.yaml");
+        fs::write(
+            &path,
+            "version: 2\nname: x\ndescription: y\nfolders:\n- path: a\n  rules:\n  - name: To b\n    conditions: []\n    actions:\n    - action_type: move\n      path: b/\n- path: b\n  rules:\n  - name: Back to a\n    conditions: []\n    actions:\n    - action_type: move\n      path: ./a\n  - name: Archive\n    conditions: []\n    actions:\n    - action_type: copy\n      path: c\n",
+        )
+        .unwrap();
+        let rule_sets = vec![config::load_config(&path, None).unwrap()];
+
+        let dot = Graph::build(&rule_sets).render(Format::Dot);
+
+        assert!(dot.contains("n0 -> n1 [label=\"To b\", color=red, penwidth=2];"));
+        assert!(dot.contains("n1 -> n0 [label=\"Back to a\", color=red, penwidth=2];"));
+        assert!(dot.contains("n1 -> n2 [label=\"Archive\", style=dashed];"));
+        let c = dir.path().canonicalize().unwrap().join("c");
+        assert!(dot.contains(&format!(
+            "n2 [label=\"{}\", style=dashed, color=darkorange, xlabel=\"unwatched\"];",
+            c.display()
+        )));
+    }
+
+    #[test]
+    fn destinations_inside_recursive_folders_are_watched_by_them() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rules.yaml");
+        fs::write(
+            &path,
+            "version: 2\nname: x\ndescription: y\nfolders:\n- path: Pictures\n  max_depth: 1\n  rules:\n  - name: Wallpapers\n    actions:\n    - {action_type: move, path: Pictures/Wallpapers}\n    - {action_type: copy, path: Pictures/a/b}\n- path: Downloads\n  rules:\n  - name: Later\n    actions:\n    - {action_type: move, path: Downloads/later}\n",
+        )
+        .unwrap();
+        let rule_sets = vec![config::load_config(&path, None).unwrap()];
+
+        let graph = Graph::build(&rule_sets);
+        let dot = graph.render(Format::Dot);
+
+        let sinks: Vec<_> = graph
+            .nodes
+            .iter()
+            .filter(|node| graph.is_sink(node))
+            .map(|node| Path::new(&node.label).file_name().unwrap())
+            .collect();
+        assert_eq!(sinks, ["b", "later"]);
+        assert!(dot.contains("n0 -> n2 [label=\"Wallpapers\", color=red, penwidth=2];"));
+        assert!(dot
+            .contains("n2 -> n0 [label=\"recursive scan\", style=dotted, color=red, penwidth=2];"));
+    }
+
+    #[test]
+    fn parent_components_and_date_patterns_lead_to_real_folders() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rules.yaml");
+        fs::write(
+            &path,
+            "version: 2\nname: x\ndescription: y\nfolders:\n- path: in\n  rules:\n  - name: Sort\n    actions:\n    - {action_type: sort_by_date, path: x/../out, pattern: '%Y/%m'}\n    - {action_type: sort_by_date, path: out, pattern: 'Photos/%Y-%m'}\n- path: out\n  rules:\n  - name: Back\n    actions:\n    - {action_type: move, path: nested/../in}\n",
+        )
+        .unwrap();
+        let rule_sets = vec![config::load_config(&path, None).unwrap()];
+
+        let graph = Graph::build(&rule_sets);
+        let dot = graph.render(Format::Dot);
+
+        let root = dir.path().canonicalize().unwrap();
+        let labels: Vec<_> = graph.nodes.iter().map(|node| node.label.clone()).collect();
+        assert_eq!(
+            labels,
+            [root.join("in"), root.join("out"), root.join("out/Photos")]
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        );
+        assert!(dot.contains("n0 -> n1 [label=\"Sort\", color=red, penwidth=2];"));
+        assert!(dot.contains("n1 -> n0 [label=\"Back\", color=red, penwidth=2];"));
+        assert_eq!(normalize("/../a/b/../../../c"), "/c");
+        assert_eq!(normalize("../a/./b/.."), "../a");
+    }
+}
@@ -0,0 +1,680 @@
+use crate::config::{
+    self, Action, Condition, ConfigError, DateComparison, FileKind, FileTime, Folder, FolderRule,
+    MatchType, OnConflict, Rule, SizeComparison, Target,
+};
+use crate::migrate;
+use crate::patterns::{self, RegexPattern};
+use crate::units::{Age, ByteSize};
+use serde::Serialize;
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub output: PathBuf,
+    /// Number of rules written to `output`.
+    pub imported: usize,
+    pub untranslated: Vec<Untranslated>,
+}
+
+/// A part of the source config that has no Orderly equivalent.
+#[derive(Debug, Serialize)]
+pub struct Untranslated {
+    pub rule: String,
+    pub item: String,
+    pub reason: String,
+}
+
+/// Translates an `organize` config into an Orderly rule file at `output`.
+///
+/// Rules with a filter that can't be translated are left out entirely, since dropping
+/// the filter would make them match more files; untranslatable actions are dropped.
+pub fn from_organize(input: &Path, output: &Path) -> Result<Report, ConfigError> {
+    let content = fs::read_to_string(input).map_err(|e| ConfigError::new(input, e))?;
+    let document: Value = config::parse_content(input, &content)?;
+    let rules = document
+        .get("rules")
+        .and_then(Value::as_sequence)
+        .ok_or_else(|| ConfigError::new(input, "no `rules:` list found"))?;
+
+    let mut folders: Vec<Folder> = Vec::new();
+    let mut untranslated = Vec::new();
+    let mut imported = 0;
+    for (index, source) in rules.iter().enumerate() {
+        let name = source
+            .get("name")
+            .and_then(Value::as_str)
+            .map(String::from)
+            .unwrap_or_else(|| format!("Rule {}", index + 1));
+        let mut importer = Importer {
+            rule: &name,
+            untranslated: &mut untranslated,
+        };
+        let (locations, rule) = match importer.rule(source) {
+            Some(translated) => translated,
+            None => continue,
+        };
+        imported += 1;
+        for mut location in locations {
+            match folders
+                .iter_mut()
+                .find(|folder| same_scan(folder, &location))
+            {
+                Some(folder) => folder.rules.push(rule.clone()),
+                None => {
+                    location.rules.push(rule.clone());
+                    folders.push(location);
+                }
+            }
+        }
+    }
+
+    let file_name = input.file_name().unwrap_or_default().to_string_lossy();
+    let rule_set = Rule {
+        version: migrate::CURRENT_VERSION,
+        name: format!("Imported from {}", file_name),
+        description: format!("Translated from the organize config {}", input.display()),
+        base_dir: None,
+        variables: BTreeMap::new(),
+        include: Vec::new(),
+        condition_sets: BTreeMap::new(),
+        snippets: BTreeMap::new(),
+        defaults: None,
+        profiles: BTreeMap::new(),
+        folders,
+        source: PathBuf::new(),
+        included: Vec::new(),
+    };
+    config::write_rule_file(output, &rule_set).map_err(|e| ConfigError::new(output, e))?;
+    Ok(Report {
+        output: output.to_path_buf(),
+        imported,
+        untranslated,
+    })
+}
+
+struct Importer<'a> {
+    rule: &'a str,
+    untranslated: &'a mut Vec<Untranslated>,
+}
+
+impl Importer<'_> {
+    fn note(&mut self, item: impl Into<String>, reason: impl Into<String>) {
+        self.untranslated.push(Untranslated {
+            rule: self.rule.to_string(),
+            item: item.into(),
+            reason: reason.into(),
+        });
+    }
+
+    /// Translates one organize rule into its locations, as folders without rules, and
+    /// an Orderly rule.
+    fn rule(&mut self, source: &Value) -> Option<(Vec<Folder>, FolderRule)> {
+        if source.get("enabled").and_then(Value::as_bool) == Some(false) {
+            self.note("enabled: false", "disabled rules are not imported");
+            return None;
+        }
+        if let Some(targets) = source.get("targets").and_then(Value::as_str) {
+            if targets != "files" {
+                self.note(
+                    format!("targets: {}", targets),
+                    "Orderly only organizes files",
+                );
+                return None;
+            }
+        }
+        let mut locations =
+            self.locations(source.get("locations").or_else(|| source.get("folders")));
+        if locations.is_empty() {
+            self.note("locations", "rule has no locations; not imported");
+            return None;
+        }
+
+        let match_type = match source.get("filter_mode").and_then(Value::as_str) {
+            None | Some("all") => None,
+            Some("any") => Some(MatchType::Any),
+            Some("none") => Some(MatchType::None),
+            Some(other) => {
+                self.note(
+                    format!("filter_mode: {}", other),
+                    "unknown filter mode; not imported",
+                );
+                return None;
+            }
+        };
+
+        let mut conditions = Vec::new();
+        let mut complete = true;
+        for filter in sequence(source.get("filters")) {
+            match filter_condition(filter) {
+                Ok(condition) => conditions.push(condition),
+                Err(reason) => {
+                    self.note(describe(filter), reason);
+                    complete = false;
+                }
+            }
+        }
+        if !complete {
+            self.note(
+                "filters",
+                "some filters could not be translated; not imported",
+            );
+            return None;
+        }
+        if conditions.is_empty() {
+            conditions.push(Condition::Always);
+        }
+
+        let mut actions = Vec::new();
+        for action in sequence(source.get("actions")) {
+            match action_of(action) {
+                Ok(translated) => actions.push(translated),
+                Err(reason) => self.note(describe(action), reason),
+            }
+            if let Ok((name, Value::Mapping(options))) = single_entry(action) {
+                for (key, value) in options.iter() {
+                    let reason = match key.as_str() {
+                        Some("dest") => continue,
+                        Some("on_conflict") if on_conflict(value).is_some() => continue,
+                        Some("on_conflict") => "no Orderly equivalent; the new file is renamed",
+                        _ => "no Orderly equivalent; ignored",
+                    };
+                    self.note(format!("{} option {}", name, describe(key)), reason);
+                }
+            }
+        }
+        if actions.is_empty() {
+            self.note("actions", "no actions could be translated; not imported");
+            return None;
+        }
+
+        // organize only uses `max_depth` when scanning subfolders, where Orderly
+        // takes it to mean the folder is scanned recursively.
+        let subfolders = source.get("subfolders").and_then(Value::as_bool) == Some(true);
+        for location in &mut locations {
+            location.recursive = subfolders;
+            if !subfolders {
+                location.max_depth = None;
+            }
+        }
+        let rule = FolderRule {
+            name: self.rule.to_string(),
+            match_type,
+            conditions,
+            actions,
+            ..FolderRule::default()
+        };
+        Some((locations, rule))
+    }
+
+    fn locations(&mut self, source: Option<&Value>) -> Vec<Folder> {
+        let mut locations = Vec::new();
+        let entries = match source {
+            Some(Value::Sequence(entries)) => entries.iter().collect(),
+            Some(entry) => vec![entry],
+            None => Vec::new(),
+        };
+        for entry in entries {
+            match entry {
+                Value::String(path) => locations.push(Folder {
+                    path: path.clone(),
+                    ..Folder::default()
+                }),
+                Value::Mapping(options) => {
+                    let path = match entry.get("path").and_then(Value::as_str) {
+                        Some(path) => path,
+                        None => {
+                            self.note(describe(entry), "location has no path");
+                            continue;
+                        }
+                    };
+                    let mut folder = Folder {
+                        path: path.to_string(),
+                        ..Folder::default()
+                    };
+                    for (key, value) in options.iter() {
+                        match (key.as_str(), value) {
+                            (Some("path"), _) => {}
+                            (Some("max_depth"), Value::Number(n)) if n.as_u64().is_some() => {
+                                folder.max_depth = n.as_u64().map(|n| n as usize);
+                            }
+                            (Some("exclude_files" | "exclude_dirs"), _) => match strings(value) {
+                                Ok(patterns) => folder.exclude.extend(patterns),
+                                Err(reason) => self.note(describe(value), reason),
+                            },
+                            _ => self.note(
+                                format!("location option {}", describe(key)),
+                                "no Orderly equivalent; ignored",
+                            ),
+                        }
+                    }
+                    locations.push(folder);
+                }
+                other => self.note(describe(other), "unrecognized location"),
+            }
+        }
+        locations
+    }
+}
+
+/// Whether two folders watch the same path with the same scan options.
+fn same_scan(a: &Folder, b: &Folder) -> bool {
+    a.path == b.path
+        && a.recursive == b.recursive
+        && a.max_depth == b.max_depth
+        && a.exclude == b.exclude
+}
+
+fn filter_condition(filter: &Value) -> Result<Condition, String> {
+    let (key, args) = single_entry(filter)?;
+    let (negated, key) = match key.strip_prefix("not ") {
+        Some(key) => (true, key.trim()),
+        None => (false, key),
+    };
+    let condition = match key.to_lowercase().as_str() {
+        // organize ignores the case of extensions, which `extension` conditions don't.
+        "extension" => {
+            let mut patterns = Vec::new();
+            for ext in strings(args)? {
+                let pattern = format!(
+                    "*.{}",
+                    globset::escape(&ext.trim_start_matches('.').to_lowercase())
+                );
+                if !patterns.contains(&pattern) {
+                    patterns.push(pattern);
+                }
+            }
+            if patterns.is_empty() {
+                return Err("an `extension` filter without extensions can't be translated".into());
+            }
+            Condition::Glob {
+                patterns,
+                target: Target::Name,
+                ignore_case: true,
+            }
+        }
+        "name" => name_condition(args)?,
+        "mimetype" => kind_condition(args)?,
+        "regex" => {
+            let pattern = match args {
+                Value::String(pattern) => pattern.as_str(),
+                _ => args
+                    .get("expr")
+                    .and_then(Value::as_str)
+                    .ok_or("regex filter without an expression")?,
+            };
+            regex_condition(pattern, Target::Name)?
+        }
+        "size" => size_condition(args)?,
+        "lastmodified" => date_condition(FileTime::Modified, args)?,
+        "created" => date_condition(FileTime::Created, args)?,
+        "date_added" => date_condition(FileTime::Added, args)?,
+        other => return Err(format!("no Orderly equivalent for the `{}` filter", other)),
+    };
+    Ok(if negated {
+        Condition::Not {
+            conditions: vec![condition],
+        }
+    } else {
+        condition
+    })
+}
+
+/// organize's `name` filter looks at the name without its extension. `contains` means
+/// the same thing on the full file name; the other options become a regex on the stem.
+fn name_condition(args: &Value) -> Result<Condition, String> {
+    let options = match args {
+        Value::Mapping(options) => options,
+        _ => return Err("name patterns have no Orderly equivalent".into()),
+    };
+    let case_sensitive = options.get("case_sensitive").and_then(Value::as_bool) != Some(false);
+    let mut conditions = Vec::new();
+    for (key, value) in options.iter() {
+        let anchor = |text: &str| match key.as_str() {
+            Some("startswith") => format!("^{}", regex::escape(text)),
+            Some("endswith") => format!("{}$", regex::escape(text)),
+            _ => regex::escape(text),
+        };
+        let mut any: Vec<Condition> = match key.as_str() {
+            Some("contains") if case_sensitive => strings(value)?
+                .into_iter()
+                .map(|value| Condition::NameContains { value })
+                .collect(),
+            Some("contains" | "startswith" | "endswith") => strings(value)?
+                .iter()
+                .map(|text| {
+                    let flags = if case_sensitive { "" } else { "(?i)" };
+                    regex_condition(&format!("{}{}", flags, anchor(text)), Target::Stem)
+                })
+                .collect::<Result<_, _>>()?,
+            Some("case_sensitive") => continue,
+            _ => {
+                return Err(format!(
+                    "the name filter's `{}` option has no Orderly equivalent",
+                    describe(key)
+                ))
+            }
+        };
+        conditions.push(match any.len() {
+            1 => any.remove(0),
+            _ => Condition::Any { conditions: any },
+        });
+    }
+    match conditions.len() {
+        0 => Err("name filter without options".into()),
+        1 => Ok(conditions.remove(0)),
+        _ => Ok(Condition::All { conditions }),
+    }
+}
+
+/// organize's `mimetype` filter takes MIME types or their top-level part. Only the parts
+/// that are also kinds translate; Orderly checks the contents where organize goes by the
+/// extension, so mislabelled files can match differently.
+fn kind_condition(args: &Value) -> Result<Condition, String> {
+    let mut kinds = Vec::new();
+    for mime in strings(args)? {
+        let kind = match mime.to_lowercase().as_str() {
+            "image" => FileKind::Image,
+            "audio" => FileKind::Audio,
+            "video" => FileKind::Video,
+            "font" => FileKind::Font,
+            _ => return Err(format!("no Orderly kind for the MIME type `{}`", mime)),
+        };
+        kinds.push(kind);
+    }
+    if kinds.is_empty() {
+        return Err("a `mimetype` filter without types can't be translated".into());
+    }
+    Ok(Condition::Kind { value: kinds })
+}
+
+fn regex_condition(pattern: &str, target: Target) -> Result<Condition, String> {
+    Ok(Condition::Regex {
+        pattern: RegexPattern::try_from(pattern.to_string())?,
+        target,
+    })
+}
+
+/// organize writes size constraints such as `>= 500 MB`, which must all hold. Its units
+/// mean the same as Orderly's.
+fn size_condition(args: &Value) -> Result<Condition, String> {
+    let mut compare = SizeComparison::default();
+    let constraints = strings(args)?;
+    if constraints.is_empty() {
+        return Err("size filter without constraints".into());
+    }
+    for constraint in constraints {
+        let constraint = constraint.trim();
+        let split = constraint
+            .find(|c: char| !"<>=".contains(c))
+            .unwrap_or(constraint.len());
+        let (operator, size) = constraint.split_at(split);
+        let slot = match operator {
+            ">" => &mut compare.greater_than,
+            ">=" => &mut compare.at_least,
+            "<" => &mut compare.less_than,
+            "<=" => &mut compare.at_most,
+            "" | "=" | "==" => &mut compare.is,
+            other => return Err(format!("unknown size operator `{}`", other)),
+        };
+        if slot.is_some() {
+            return Err(format!("`{}` repeats a size comparison", constraint));
+        }
+        *slot = Some(ByteSize::parse(size)?);
+    }
+    Ok(Condition::Size(Box::new(compare)))
+}
+
+/// organize's date filters match files older than the duration they're given, or newer
+/// with `mode: newer`. Months and years have no fixed length, so they aren't translated.
+fn date_condition(time: FileTime, args: &Value) -> Result<Condition, String> {
+    let options = match args {
+        Value::Mapping(options) => options,
+        _ => return Err("date filter without a duration".into()),
+    };
+    let mut age = String::new();
+    let mut newer = false;
+    for (key, value) in options.iter() {
+        let unit = match key.as_str() {
+            Some("weeks") => "w",
+            Some("days") => "d",
+            Some("hours") => "h",
+            Some("minutes") => "m",
+            Some("seconds") => "s",
+            Some("mode") => {
+                newer = match value.as_str() {
+                    Some("older") => false,
+                    Some("newer") => true,
+                    _ => return Err(format!("unknown date filter mode {}", describe(value))),
+                };
+                continue;
+            }
+            _ => {
+                return Err(format!(
+                    "the date filter's `{}` option has no Orderly equivalent",
+                    describe(key)
+                ))
+            }
+        };
+        match value.as_u64() {
+            Some(0) => {}
+            Some(n) => age.push_str(&format!("{}{}", n, unit)),
+            None => return Err(format!("{} is not a whole number", describe(value))),
+        }
+    }
+    if age.is_empty() {
+        return Err("date filter without a duration".into());
+    }
+    let age = Some(Age::parse(&age)?);
+    let compare = if newer {
+        DateComparison {
+            time,
+            within: age,
+            ..DateComparison::default()
+        }
+    } else {
+        DateComparison {
+            time,
+            older_than: age,
+            ..DateComparison::default()
+        }
+    };
+    Ok(Condition::Date(Box::new(compare)))
+}
+
+fn action_of(action: &Value) -> Result<Action, String> {
+    let (key, args) = single_entry(action)?;
+    match key.to_lowercase().as_str() {
+        "delete" | "trash" => Ok(Action::Delete),
+        "move" => Ok(Action::Move {
+            path: destination(args)?,
+            on_conflict: Some(conflict_policy(args)),
+        }),
+        "copy" => Ok(Action::Copy {
+            path: destination(args)?,
+            on_conflict: Some(conflict_policy(args)),
+        }),
+        other => Err(format!("no Orderly equivalent for the `{}` action", other)),
+    }
+}
+
+/// How a `move` or `copy` handles an existing file. organize renames the new file
+/// unless told otherwise, and policies Orderly lacks fall back to that.
+fn conflict_policy(args: &Value) -> OnConflict {
+    args.get("on_conflict")
+        .map_or(Some(OnConflict::Rename), on_conflict)
+        .unwrap_or(OnConflict::Rename)
+}
+
+fn on_conflict(value: &Value) -> Option<OnConflict> {
+    match value.as_str()? {
+        "rename_new" => Some(OnConflict::Rename),
+        "skip" => Some(OnConflict::Skip),
+        "overwrite" => Some(OnConflict::Overwrite),
+        _ => None,
+    }
+}
+
+/// The folder a `move` or `copy` puts files into. organize treats a destination without
+/// a trailing slash as the new file name, which Orderly can't do.
+fn destination(args: &Value) -> Result<String, String> {
+    let dest = match args {
+        Value::String(dest) => dest.as_str(),
+        _ => args
+            .get("dest")
+            .and_then(Value::as_str)
+            .ok_or("destination is missing")?,
+    };
+    // organize refers to regex groups as `{regex.name}`, Orderly as `{name}`.
+    let dest = dest.replace("{regex.", "{");
+    if patterns::template_names(&dest).len() != dest.matches('{').count() {
+        return Err("destinations with placeholders have no Orderly equivalent".into());
+    }
+    if !dest.ends_with('/') {
+        return Err("destinations that rename the file have no Orderly equivalent".into());
+    }
+    Ok(dest.trim_end_matches('/').to_string())
+}
+
+/// Splits a filter or action written as `name` or `name: arguments`.
+fn single_entry(item: &Value) -> Result<(&str, &Value), String> {
+    const NONE: &Value = &Value::Null;
+    match item {
+        Value::String(key) => Ok((key.trim(), NONE)),
+        Value::Mapping(entries) if entries.len() == 1 => {
+            let (key, args) = entries.iter().next().unwrap();
+            let key = key.as_str().ok_or("expected a name")?;
+            Ok((key.trim(), args))
+        }
+        _ => Err("expected a name or a single `name: arguments` entry".into()),
+    }
+}
+
+fn strings(value: &Value) -> Result<Vec<String>, String> {
+    let values = match value {
+        Value::Sequence(values) => values.iter().collect(),
+        value => vec![value],
+    };
+    values
+        .into_iter()
+        .map(|value| match value {
+            Value::String(s) => Ok(s.clone()),
+            Value::Number(n) => Ok(n.to_string()),
+            other => Err(format!("expected text, found {}", describe(other))),
+        })
+        .collect()
+}
+
+fn sequence(value: Option<&Value>) -> Vec<&Value> {
+    match value {
+        Some(Value::Sequence(items)) => items.iter().collect(),
+        Some(Value::Null) | None => Vec::new(),
+        Some(item) => vec![item],
+    }
+}
+
+fn describe(value: &Value) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conditions::{self, Entry, SizeRange};
+    use tempfile::tempdir;
+
+    #[test]
+    fn translates_organize_rules_and_reports_the_rest() {
+        let dir = tempdir().unwrap();
+        let input = dir.path().join("config.yaml");
+        fs::write(
+            &input,
+            r#"
+rules:
+  - name: PDFs
+    locations: ~/Downloads
+    filters:
+      - extension: [pdf, .PDF]
+      - not name:
+          contains: draft
+      - size: [">= 100kb", "< 2 MB"]
+      - lastmodified:
+          days: 7
+          hours: 12
+          mode: newer
+    actions:
+      - echo: "Found {path}"
+      - move: ~/Documents/PDFs/
+  - name: Invoices
+    locations: ~/Downloads
+    filters:
+      - regex: 'INV-(?P<client>\w+)-.*\.pdf'
+    actions:
+      - move: ~/Documents/Invoices/{regex.client}/
+  - name: Pictures
+    locations: ~/Downloads
+    filters:
+      - mimetype: image
+    actions:
+      - move: ~/Pictures/
+  - name: Old files
+    locations:
+      - path: ~/Downloads
+    filters:
+      - lastmodified:
+          months: 3
+    actions:
+      - delete
+"#,
+        )
+        .unwrap();
+        let output = dir.path().join("rules/imported.yaml");
+
+        let report = from_organize(&input, &output).unwrap();
+
+        assert_eq!(report.imported, 3);
+        let items: Vec<_> = report
+            .untranslated
+            .iter()
+            .map(|u| (u.rule.as_str(), u.item.as_str()))
+            .collect();
+        assert_eq!(
+            items,
+            [
+                ("PDFs", r#"{"echo":"Found {path}"}"#),
+                ("Old files", r#"{"lastmodified":{"months":3}}"#),
+                ("Old files", "filters"),
+            ]
+        );
+        let rule_set = config::load_config(&output, None).unwrap();
+        let rule = &rule_set.folders[0].rules[0];
+        assert!(
+            matches!(&rule.conditions[..], [Condition::Glob { patterns, ignore_case: true, .. }, Condition::Not { .. }, Condition::Size(compare), Condition::Date(date)]
+                if patterns == &["*.pdf"] && SizeRange::new(compare).min == 100_000 && SizeRange::new(compare).max == 1_999_999
+                    && date.within.as_ref().map(|age| age.seconds) == Some(7 * 86_400 + 12 * 3_600))
+        );
+        let pdf = conditions::create_condition(&rule.conditions[0]).unwrap();
+        assert!(pdf.evaluate(&Entry::new(&dir.path().join("SCAN.Pdf"), dir.path())));
+        assert!(
+            matches!(&rule.actions[..], [Action::Move { path, .. }] if path.ends_with("Documents/PDFs"))
+        );
+        let invoices = &rule_set.folders[0].rules[1];
+        assert!(matches!(
+            &invoices.conditions[..],
+            [Condition::Regex {
+                target: Target::Name,
+                ..
+            }]
+        ));
+        assert!(
+            matches!(&invoices.actions[..], [Action::Move { path, .. }] if path.ends_with("Documents/Invoices/{client}"))
+        );
+        assert!(matches!(
+            &rule_set.folders[0].rules[2].conditions[..],
+            [Condition::Kind { value }] if value == &[FileKind::Image]
+        ));
+    }
+}
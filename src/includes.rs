@@ -0,0 +1,628 @@
+use crate::config::{self, Condition, ConfigError, Defaults, Folder, FolderRule, Rule};
+use crate::paths;
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+/// The parts of a file that `include:` can pull in. Any rule file can be included;
+/// its name and description are ignored, and settings that only make sense for a
+/// whole rule set are rejected.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Fragment {
+    #[serde(default, rename = "version")]
+    _version: Option<IgnoredAny>,
+    #[serde(default, rename = "name")]
+    _name: Option<IgnoredAny>,
+    #[serde(default, rename = "description")]
+    _description: Option<IgnoredAny>,
+    #[serde(default)]
+    base_dir: Option<IgnoredAny>,
+    #[serde(default)]
+    variables: Option<IgnoredAny>,
+    #[serde(default)]
+    defaults: Option<IgnoredAny>,
+    #[serde(default)]
+    profiles: Option<IgnoredAny>,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    condition_sets: BTreeMap<String, Vec<Condition>>,
+    #[serde(default)]
+    snippets: BTreeMap<String, FolderRule>,
+    #[serde(default)]
+    folders: Vec<Folder>,
+    #[serde(default)]
+    rules: Vec<FolderRule>,
+}
+
+impl Fragment {
+    /// Rejects settings that only apply to a whole rule set.
+    fn check_supported(&self) -> Result<(), String> {
+        let unsupported = [
+            ("base_dir", self.base_dir.is_some()),
+            ("variables", self.variables.is_some()),
+            ("defaults", self.defaults.is_some()),
+            ("profiles", self.profiles.is_some()),
+        ];
+        match unsupported.iter().find(|(_, set)| *set) {
+            Some((key, _)) => Err(format!("`{}` is not supported in included files", key)),
+            None => Ok(()),
+        }
+    }
+}
+
+struct Definition<T> {
+    value: T,
+    source: PathBuf,
+}
+
+struct Resolver<'a> {
+    variables: &'a BTreeMap<String, String>,
+    /// Files being loaded, outermost first, for cycle detection and error context.
+    chain: Vec<PathBuf>,
+    included: Vec<PathBuf>,
+    condition_sets: BTreeMap<String, Definition<Vec<Condition>>>,
+    snippets: BTreeMap<String, Definition<FolderRule>>,
+}
+
+/// Pulls in `include:` files and replaces snippet and condition set references
+/// with what they name, so the engine only ever sees plain rules.
+///
+/// A file can use the condition sets and snippets it defines or includes, as well
+/// as those of the files that include it.
+pub fn resolve(rule: &mut Rule) -> Result<(), ConfigError> {
+    let source = rule.source.clone();
+    let mut resolver = Resolver {
+        variables: &rule.variables,
+        chain: vec![canonical(&source)],
+        included: Vec::new(),
+        condition_sets: BTreeMap::new(),
+        snippets: BTreeMap::new(),
+    };
+    let fragment = Fragment {
+        include: mem::take(&mut rule.include),
+        condition_sets: mem::take(&mut rule.condition_sets),
+        snippets: mem::take(&mut rule.snippets),
+        folders: mem::take(&mut rule.folders),
+        ..Fragment::default()
+    };
+    let fragment = resolver.expand(&source, fragment)?;
+    if let Some(defaults) = &mut rule.defaults {
+        resolver.resolve_defaults(&source, defaults)?;
+    }
+    rule.folders = fragment.folders;
+    rule.included = resolver.included;
+    Ok(())
+}
+
+/// Checks that `content` would load as a file pulled in with `include:`.
+pub fn parse_fragment(path: &Path, content: &str) -> Result<(), ConfigError> {
+    let fragment: Fragment = config::parse_content(path, content)?;
+    fragment
+        .check_supported()
+        .map_err(|e| ConfigError::new(path, e))
+}
+
+/// Files that `path` includes, directly or indirectly, found by scanning the raw
+/// YAML. Works even when the file doesn't load, so fragments can always be told
+/// apart from rule sets.
+///
+/// A file in an include cycle lists itself.
+pub fn declared_includes(path: &Path) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    collect_includes(&canonical(path), &mut found);
+    found
+}
+
+fn collect_includes(path: &Path, found: &mut Vec<PathBuf>) {
+    let value: serde_yaml::Value = match config::parse_file(path) {
+        Ok(value) => value,
+        Err(_) => return,
+    };
+    let folders = value.get("folders").and_then(|f| f.as_sequence());
+    let lists = std::iter::once(value.get("include"))
+        .chain(folders.into_iter().flatten().map(|f| f.get("include")));
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+
+    for include in lists.flatten().filter_map(|l| l.as_sequence()).flatten() {
+        let include = match include.as_str().map(|i| paths::expand(i, &BTreeMap::new())) {
+            Some(Ok(include)) => base.join(include),
+            _ => continue,
+        };
+        if let Ok(include) = include.canonicalize() {
+            if !found.contains(&include) {
+                found.push(include.clone());
+                collect_includes(&include, found);
+            }
+        }
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+impl Resolver<'_> {
+    /// An error in `path`, noting which files included it.
+    fn error(&self, path: &Path, message: impl fmt::Display) -> ConfigError {
+        self.context(ConfigError::new(path, message))
+    }
+
+    fn context(&self, mut error: ConfigError) -> ConfigError {
+        let path = canonical(&error.path);
+        let includers = self
+            .chain
+            .iter()
+            .position(|p| *p == path)
+            .unwrap_or(self.chain.len());
+        if includers > 0 {
+            let chain: Vec<_> = self.chain[..includers]
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect();
+            error.message = format!("{} (included from {})", error.message, chain.join(" -> "));
+        }
+        error
+    }
+
+    /// Loads the fragment that `include` names, relative to the file `from`.
+    fn include(&mut self, from: &Path, include: &str) -> Result<Fragment, ConfigError> {
+        let expanded = paths::expand(include, self.variables)
+            .map_err(|e| self.error(from, format!("include `{}`: {}", include, e)))?;
+        let base = from.parent().unwrap_or_else(|| Path::new(""));
+        let path = base.join(expanded);
+        let path = path
+            .canonicalize()
+            .map_err(|e| self.error(from, format!("include `{}`: {}", include, e)))?;
+
+        if self.chain.contains(&path) {
+            let mut cycle: Vec<_> = self.chain.iter().map(|p| p.display().to_string()).collect();
+            cycle.push(path.display().to_string());
+            return Err(ConfigError::new(
+                from,
+                format!("include cycle: {}", cycle.join(" -> ")),
+            ));
+        }
+
+        let mut fragment: Fragment = config::parse_file(&path).map_err(|e| self.context(e))?;
+        fragment
+            .check_supported()
+            .map_err(|e| self.error(&path, e))?;
+        // Relative paths in the fragment start from its own directory, wherever its
+        // snippets end up being used.
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        for snippet in fragment.snippets.values_mut() {
+            snippet.base = Some(dir.clone());
+        }
+        self.chain.push(path.clone());
+        if !self.included.contains(&path) {
+            self.included.push(path.clone());
+        }
+        let fragment = self.expand(&path, fragment);
+        self.chain.pop();
+        let mut fragment = fragment?;
+        for folder in &mut fragment.folders {
+            folder.base.get_or_insert_with(|| dir.clone());
+        }
+        for rule in &mut fragment.rules {
+            rule.base.get_or_insert_with(|| dir.clone());
+        }
+        Ok(fragment)
+    }
+
+    /// Registers a file's definitions, pulls in its includes and resolves its rules.
+    /// Included folders and rules come before the file's own.
+    fn expand(&mut self, source: &Path, mut fragment: Fragment) -> Result<Fragment, ConfigError> {
+        for (name, conditions) in mem::take(&mut fragment.condition_sets) {
+            let existing = self.condition_sets.get(&name).map(|d| &d.source);
+            self.check_unique("condition set", &name, existing, source)?;
+            self.condition_sets.insert(
+                name,
+                Definition {
+                    value: conditions,
+                    source: source.to_path_buf(),
+                },
+            );
+        }
+        for (name, snippet) in mem::take(&mut fragment.snippets) {
+            let existing = self.snippets.get(&name).map(|d| &d.source);
+            self.check_unique("snippet", &name, existing, source)?;
+            self.snippets.insert(
+                name,
+                Definition {
+                    value: snippet,
+                    source: source.to_path_buf(),
+                },
+            );
+        }
+
+        let mut folders = Vec::new();
+        let mut rules = Vec::new();
+        for include in &fragment.include {
+            let included = self.include(source, include)?;
+            folders.extend(included.folders);
+            rules.extend(included.rules);
+        }
+        for mut folder in fragment.folders {
+            self.resolve_folder(source, &mut folder)?;
+            folders.push(folder);
+        }
+        for mut rule in fragment.rules {
+            self.resolve_rule(source, &mut rule)?;
+            rules.push(rule);
+        }
+        Ok(Fragment {
+            folders,
+            rules,
+            ..Fragment::default()
+        })
+    }
+
+    fn check_unique(
+        &self,
+        kind: &str,
+        name: &str,
+        existing: Option<&PathBuf>,
+        source: &Path,
+    ) -> Result<(), ConfigError> {
+        match existing {
+            // The same file reached through two includes defines the same things.
+            Some(existing) if existing != source => Err(self.error(
+                source,
+                format!(
+                    "{} `{}` is already defined in {}",
+                    kind,
+                    name,
+                    existing.display()
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn resolve_folder(&mut self, source: &Path, folder: &mut Folder) -> Result<(), ConfigError> {
+        if let Some(defaults) = &mut folder.defaults {
+            self.resolve_defaults(source, defaults)
+                .map_err(|e| ConfigError {
+                    message: format!("folder `{}`: {}", folder.path, e.message),
+                    ..e
+                })?;
+        }
+        let mut rules = Vec::new();
+        for include in mem::take(&mut folder.include) {
+            rules.extend(self.include(source, &include)?.rules);
+        }
+        for mut rule in mem::take(&mut folder.rules) {
+            self.resolve_rule(source, &mut rule)
+                .map_err(|e| ConfigError {
+                    message: format!("folder `{}`: {}", folder.path, e.message),
+                    ..e
+                })?;
+            rules.push(rule);
+        }
+        folder.rules = rules;
+        Ok(())
+    }
+
+    fn resolve_defaults(&self, source: &Path, defaults: &mut Defaults) -> Result<(), ConfigError> {
+        for condition in &mut defaults.conditions {
+            self.resolve_condition(condition, &mut Vec::new())
+                .map_err(|e| self.error(source, format!("defaults: {}", e)))?;
+        }
+        Ok(())
+    }
+
+    fn resolve_rule(&self, source: &Path, rule: &mut FolderRule) -> Result<(), ConfigError> {
+        if let Some(name) = rule.snippet.take() {
+            self.apply_snippet(rule, &name, &mut Vec::new())
+                .map_err(|e| self.error(source, e))?;
+        }
+        if rule.name.is_empty() {
+            return Err(self.error(
+                source,
+                "rule has no `name` and no `use:` snippet that provides one",
+            ));
+        }
+        let rule_name = &rule.name;
+        for condition in &mut rule.conditions {
+            self.resolve_condition(condition, &mut Vec::new())
+                .map_err(|e| self.error(source, format!("rule `{}`: {}", rule_name, e)))?;
+        }
+        Ok(())
+    }
+
+    /// Fills the fields `rule` leaves unset from the snippet `name`.
+    fn apply_snippet(
+        &self,
+        rule: &mut FolderRule,
+        name: &str,
+        stack: &mut Vec<String>,
+    ) -> Result<(), String> {
+        let definition = self
+            .snippets
+            .get(name)
+            .ok_or_else(|| format!("unknown snippet `{}`", name))?;
+        if stack.iter().any(|seen| seen == name) {
+            stack.push(name.to_string());
+            return Err(format!("snippet cycle: {}", stack.join(" -> ")));
+        }
+        stack.push(name.to_string());
+
+        let mut base = definition.value.clone();
+        if let Some(inner) = base.snippet.take() {
+            self.apply_snippet(&mut base, &inner, stack).map_err(|e| {
+                format!(
+                    "snippet `{}` from {}: {}",
+                    name,
+                    definition.source.display(),
+                    e
+                )
+            })?;
+        }
+        if rule.name.is_empty() {
+            rule.name = base.name;
+        }
+        if rule.match_type.is_none() {
+            rule.match_type = base.match_type;
+        }
+        if rule.priority.is_none() {
+            rule.priority = base.priority;
+        }
+        if rule.stop.is_none() {
+            rule.stop = base.stop;
+        }
+        if rule.inherit_defaults.is_none() {
+            rule.inherit_defaults = base.inherit_defaults;
+        }
+        if rule.enabled.is_none() {
+            rule.enabled = base.enabled;
+        }
+        if rule.active_when.is_none() {
+            rule.active_when = base.active_when;
+        }
+        if rule.inactive_when.is_none() {
+            rule.inactive_when = base.inactive_when;
+        }
+        if rule.conditions.is_empty() {
+            rule.conditions = base.conditions;
+        }
+        if rule.actions.is_empty() {
+            // The snippet's action paths start from the file that defines it.
+            rule.actions = base.actions;
+            rule.base = base.base;
+        }
+        stack.pop();
+        Ok(())
+    }
+
+    /// Replaces condition set references, at any depth, with an `all` group of the set.
+    fn resolve_condition(
+        &self,
+        condition: &mut Condition,
+        stack: &mut Vec<String>,
+    ) -> Result<(), String> {
+        match condition {
+            Condition::All { conditions }
+            | Condition::Any { conditions }
+            | Condition::Not { conditions } => {
+                for condition in conditions {
+                    self.resolve_condition(condition, stack)?;
+                }
+            }
+            Condition::Set { name } => {
+                let definition = self
+                    .condition_sets
+                    .get(name.as_str())
+                    .ok_or_else(|| format!("unknown condition set `{}`", name))?;
+                if stack.iter().any(|seen| seen == name) {
+                    stack.push(name.clone());
+                    return Err(format!("condition set cycle: {}", stack.join(" -> ")));
+                }
+                stack.push(name.clone());
+                let mut conditions = definition.value.clone();
+                for condition in &mut conditions {
+                    self.resolve_condition(condition, stack).map_err(|e| {
+                        format!(
+                            "condition set `{}` from {}: {}",
+                            name,
+                            definition.source.display(),
+                            e
+                        )
+                    })?;
+                }
+                stack.pop();
+                *condition = Condition::All { conditions };
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{self, Action, Condition};
+    use std::fs;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    #[test]
+    fn resolves_includes_snippets_and_condition_sets() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("shared")).unwrap();
+        fs::write(
+            dir.path().join("main.yaml"),
+            "name: Main\ndescription: d\ninclude: [shared/sets.yaml]\nfolders:\n- path: in\n  include: [shared/rules.yaml]\n  rules:\n  - use: music\n    name: Music\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("shared/sets.yaml"),
+            "condition_sets:\n  audio:\n  - condition_type: extension\n    value: mp3\nsnippets:\n  music:\n    name: Unused\n    conditions:\n    - condition_type: condition_set\n      name: audio\n    actions:\n    - action_type: move\n      path: Music\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("shared/rules.yaml"),
+            "rules:\n- name: Documents\n  conditions: []\n  actions:\n  - action_type: delete\n",
+        )
+        .unwrap();
+
+        let (rules, errors) = config::load_all(dir.path(), None);
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(rules.len(), 1);
+        let folder = &rules[0].folders[0];
+        let names: Vec<_> = folder.rules.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["Documents", "Music"]);
+        assert!(matches!(
+            folder.rules[1].conditions.as_slice(),
+            [Condition::All { conditions }] if matches!(conditions.as_slice(), [Condition::Extension { .. }])
+        ));
+        assert!(matches!(
+            folder.rules[1].actions.as_slice(),
+            [Action::Move { .. }]
+        ));
+        assert_eq!(rules[0].included.len(), 2);
+    }
+
+    #[test]
+    fn reports_include_cycles() {
+        let dir = tempdir().unwrap();
+        let main = dir.path().join("main.yaml");
+        fs::write(
+            &main,
+            "name: Main\ndescription: d\ninclude: [other.yaml]\nfolders: []\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("other.yaml"), "include: [main.yaml]\n").unwrap();
+
+        let err = config::load_config(&main, None).unwrap_err();
+
+        assert!(err.message.starts_with("include cycle: "), "{}", err);
+        assert!(err.message.ends_with("main.yaml"), "{}", err);
+    }
+
+    #[test]
+    fn included_files_reject_unknown_and_rule_set_only_keys() {
+        let dir = tempdir().unwrap();
+        let main = dir.path().join("main.yaml");
+        fs::write(
+            &main,
+            "name: Main\ndescription: d\ninclude: [frag.yaml]\nfolders: []\n",
+        )
+        .unwrap();
+        let load = |fragment: &str| {
+            fs::write(dir.path().join("frag.yaml"), fragment).unwrap();
+            config::load_config(&main, None)
+                .map(|_| ())
+                .map_err(|e| e.message)
+        };
+
+        let typo = load("snipets: {}\n").unwrap_err();
+        let defaults = load("defaults:\n  exclude: ['*.tmp']\n").unwrap_err();
+        let variables = load("variables:\n  home: /tmp\n").unwrap_err();
+
+        assert!(typo.contains("unknown field `snipets`"), "{}", typo);
+        assert!(
+            defaults.starts_with("`defaults` is not supported in included files"),
+            "{}",
+            defaults
+        );
+        assert!(variables.contains("(included from "), "{}", variables);
+        load("version: 2\nname: Shared\ndescription: d\nfolders: []\n").unwrap();
+    }
+
+    #[test]
+    fn included_paths_start_from_the_fragment() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("shared/nested")).unwrap();
+        let main = root.join("main.yaml");
+        fs::write(
+            &main,
+            "name: Main\ndescription: d\ninclude: [shared/frag.yaml]\nfolders:\n- path: in\n  include: [shared/nested/rules.yaml]\n  rules:\n  - name: Own\n    actions:\n    - {action_type: move, path: own}\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("shared/frag.yaml"),
+            "folders:\n- path: inbox\n  rules:\n  - name: Frag\n    actions:\n    - {action_type: move, path: done}\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("shared/nested/rules.yaml"),
+            "rules:\n- name: Nested\n  actions:\n  - {action_type: copy, path: copies}\n",
+        )
+        .unwrap();
+
+        let rule = config::load_config(&main, None).unwrap();
+
+        let destinations: Vec<_> = rule
+            .folders
+            .iter()
+            .flat_map(|folder| &folder.rules)
+            .flat_map(|rule| &rule.actions)
+            .map(|action| match action {
+                Action::Move { path, .. } | Action::Copy { path, .. } => Path::new(path),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(Path::new(&rule.folders[0].path), root.join("shared/inbox"));
+        assert_eq!(Path::new(&rule.folders[1].path), root.join("in"));
+        assert_eq!(
+            destinations,
+            [
+                root.join("shared/done"),
+                root.join("shared/nested/copies"),
+                root.join("own")
+            ]
+        );
+    }
+
+    #[test]
+    fn snippet_paths_start_from_the_file_defining_them() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("rules")).unwrap();
+        fs::create_dir_all(root.join("shared/media")).unwrap();
+        let main = root.join("rules/main.yaml");
+        fs::write(
+            &main,
+            "name: Main\ndescription: d\ninclude: [../shared/media/snippets.yaml]\nfolders:\n- path: in\n  rules:\n  - use: music\n  - use: music\n    name: Own actions\n    actions:\n    - {action_type: move, path: own}\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("shared/media/snippets.yaml"),
+            "include: [../chained.yaml]\nsnippets:\n  music:\n    name: Music\n    actions:\n    - {action_type: move, path: Music}\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("shared/chained.yaml"),
+            "folders:\n- path: inbox\n  rules:\n  - use: music\n",
+        )
+        .unwrap();
+
+        let rule = config::load_config(&main, None).unwrap();
+
+        let destinations: Vec<_> = rule
+            .folders
+            .iter()
+            .flat_map(|folder| &folder.rules)
+            .flat_map(|rule| &rule.actions)
+            .map(|action| match action {
+                Action::Move { path, .. } => Path::new(path),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            destinations,
+            [
+                root.join("shared/media/Music"),
+                root.join("shared/media/Music"),
+                root.join("rules/own")
+            ]
+        );
+    }
+}
@@ -0,0 +1,470 @@
+use crate::config::{self, Action, Condition, FileKind, Folder, FolderRule, Rule};
+use crate::{migrate, paths};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// A starting point for a rule file, with the folders it asks the user for.
+pub struct Template {
+    pub name: &'static str,
+    title: &'static str,
+    pub description: &'static str,
+    /// Where relative paths start from, relative to the written rule file.
+    base_dir: Option<&'static str>,
+    /// Variables the rules refer to: name, question and default path.
+    variables: &'static [(&'static str, &'static str, &'static str)],
+    build: fn() -> Vec<Folder>,
+}
+
+pub const TEMPLATES: &[Template] = &[
+    Template {
+        name: "downloads",
+        title: "Downloads cleanup",
+        description: "Sort downloads into music, video, document and picture folders",
+        base_dir: None,
+        variables: &[
+            ("downloads", "Downloads folder", "~/Downloads"),
+            ("music", "Music folder", "~/Music"),
+            ("videos", "Videos folder", "~/Videos"),
+            ("documents", "Documents folder", "~/Documents"),
+            ("pictures", "Pictures folder", "~/Pictures"),
+        ],
+        build: downloads,
+    },
+    Template {
+        name: "photos",
+        title: "Photo library",
+        description: "File imported photos and videos into a library by date",
+        base_dir: None,
+        variables: &[
+            (
+                "import",
+                "Folder photos are imported into",
+                "~/Pictures/Import",
+            ),
+            ("library", "Photo library", "~/Pictures/Library"),
+        ],
+        build: photos,
+    },
+    Template {
+        name: "screenshots",
+        title: "Screenshots",
+        description: "Move screenshots and screen recordings off the desktop",
+        base_dir: None,
+        variables: &[
+            ("desktop", "Folder screenshots are saved to", "~/Desktop"),
+            (
+                "screenshots",
+                "Screenshots folder",
+                "~/Pictures/Screenshots",
+            ),
+        ],
+        build: screenshots,
+    },
+    Template {
+        name: "dev-scratch",
+        title: "Developer scratch",
+        description: "Keep a developer scratch folder free of logs and old archives",
+        base_dir: None,
+        variables: &[
+            ("scratch", "Scratch folder", "~/scratch"),
+            (
+                "archive",
+                "Folder for archives and patches",
+                "~/scratch/archive",
+            ),
+        ],
+        build: dev_scratch,
+    },
+    Template {
+        name: "invoices",
+        title: "Invoices",
+        description: "File invoices and receipts by year",
+        base_dir: None,
+        variables: &[
+            ("inbox", "Folder invoices arrive in", "~/Downloads"),
+            ("invoices", "Invoices folder", "~/Documents/Invoices"),
+        ],
+        build: invoices,
+    },
+    Template {
+        name: "example",
+        title: "Orderly Sandbox Rules",
+        description: "Sandbox rules for trying Orderly out in ./test_folder",
+        // Written to `rules/`, next to the `test_folder` the setup scripts create.
+        base_dir: Some(".."),
+        variables: &[],
+        build: example,
+    },
+];
+
+pub fn template(name: &str) -> Option<&'static Template> {
+    TEMPLATES.iter().find(|t| t.name == name)
+}
+
+impl Template {
+    /// Names of the variables the template asks for.
+    pub fn variable_names(&self) -> impl Iterator<Item = &'static str> {
+        self.variables.iter().map(|&(name, _, _)| name)
+    }
+
+    /// Builds the template's rule set, taking variable values from `values` or the defaults.
+    pub fn rule_set(&self, values: &BTreeMap<String, String>) -> Rule {
+        let variables = self
+            .variables
+            .iter()
+            .map(|&(name, _, default)| {
+                let value = values.get(name).map_or(default, String::as_str);
+                (name.to_string(), value.to_string())
+            })
+            .collect();
+        Rule {
+            version: migrate::CURRENT_VERSION,
+            name: self.title.to_string(),
+            description: self.description.to_string(),
+            base_dir: self.base_dir.map(String::from),
+            variables,
+            include: Vec::new(),
+            condition_sets: BTreeMap::new(),
+            snippets: BTreeMap::new(),
+            defaults: None,
+            profiles: BTreeMap::new(),
+            folders: (self.build)(),
+            source: PathBuf::new(),
+            included: Vec::new(),
+        }
+    }
+}
+
+/// Where `init` writes a template: the config file itself, or `<template>.yaml` inside
+/// the config directory.
+pub fn destination(config_path: &Path, template: &Template) -> PathBuf {
+    if config::is_rule_file(config_path) {
+        config_path.to_path_buf()
+    } else {
+        config_path.join(format!("{}.yaml", template.name))
+    }
+}
+
+/// Asks which template to use and for each of its folders, starting from `values`.
+pub fn wizard(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    mut values: BTreeMap<String, String>,
+) -> io::Result<(&'static Template, BTreeMap<String, String>)> {
+    writeln!(output, "Templates:")?;
+    for (i, template) in TEMPLATES.iter().enumerate() {
+        writeln!(
+            output,
+            "  {}. {:<12} {}",
+            i + 1,
+            template.name,
+            template.description
+        )?;
+    }
+    let template = loop {
+        let answer = ask(input, output, "Template", TEMPLATES[0].name)?;
+        let chosen = match answer.parse::<usize>() {
+            Ok(number) => number.checked_sub(1).and_then(|i| TEMPLATES.get(i)),
+            Err(_) => template(&answer),
+        };
+        match chosen {
+            Some(template) => break template,
+            None => writeln!(output, "No template called `{}`", answer)?,
+        }
+    };
+
+    for &(name, question, default) in template.variables {
+        if values.contains_key(name) {
+            continue;
+        }
+        let answer = ask(input, output, question, default)?;
+        match paths::expand(&answer, &values) {
+            Ok(path) if !Path::new(&path).is_dir() => writeln!(
+                output,
+                "  {} doesn't exist yet; create it before running Orderly",
+                path
+            )?,
+            Ok(_) => {}
+            Err(e) => writeln!(output, "  {}", e)?,
+        }
+        values.insert(name.to_string(), answer);
+    }
+    Ok((template, values))
+}
+
+/// Prints a question and reads one line, falling back to `default` on an empty answer.
+fn ask(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    question: &str,
+    default: &str,
+) -> io::Result<String> {
+    write!(output, "{} [{}]: ", question, default)?;
+    output.flush()?;
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "input ended before the wizard finished",
+        ));
+    }
+    let answer = line.trim();
+    Ok(if answer.is_empty() { default } else { answer }.to_string())
+}
+
+fn folder(path: &str, rules: Vec<FolderRule>) -> Folder {
+    Folder {
+        path: path.into(),
+        rules,
+        ..Folder::default()
+    }
+}
+
+fn rule(name: &str, conditions: Vec<Condition>, actions: Vec<Action>) -> FolderRule {
+    FolderRule {
+        name: name.into(),
+        conditions,
+        actions,
+        ..FolderRule::default()
+    }
+}
+
+fn extension(list: &str) -> Condition {
+    Condition::Extension {
+        value: list.split(',').map(String::from).collect(),
+    }
+}
+
+fn kind(kind: FileKind) -> Condition {
+    Condition::Kind { value: vec![kind] }
+}
+
+fn name_contains(value: &str) -> Condition {
+    Condition::NameContains {
+        value: value.into(),
+    }
+}
+
+fn move_to(path: &str) -> Action {
+    Action::Move {
+        path: path.into(),
+        on_conflict: None,
+    }
+}
+
+fn sort_by_date(path: &str, pattern: &str) -> Action {
+    Action::SortByDate {
+        path: path.into(),
+        pattern: Some(pattern.into()),
+        on_conflict: None,
+    }
+}
+
+fn downloads() -> Vec<Folder> {
+    vec![folder(
+        "$downloads",
+        vec![
+            rule(
+                "Move music files to Music folder",
+                vec![kind(FileKind::Audio)],
+                vec![move_to("$music")],
+            ),
+            rule(
+                "Move video files to Videos folder",
+                vec![kind(FileKind::Video)],
+                vec![move_to("$videos")],
+            ),
+            rule(
+                "Move document files to Documents folder",
+                vec![kind(FileKind::Document)],
+                vec![move_to("$documents")],
+            ),
+            rule(
+                "Move picture files to Pictures folder",
+                vec![kind(FileKind::Image)],
+                vec![move_to("$pictures")],
+            ),
+            rule(
+                "Move installers to Installers subfolder",
+                vec![kind(FileKind::Executable)],
+                vec![move_to("$downloads/Installers")],
+            ),
+            rule(
+                "Move archives to Archives subfolder",
+                vec![kind(FileKind::Archive)],
+                vec![move_to("$downloads/Archives")],
+            ),
+        ],
+    )]
+}
+
+fn photos() -> Vec<Folder> {
+    vec![folder(
+        "$import",
+        vec![
+            rule(
+                "Sort photos into year/month folders",
+                vec![kind(FileKind::Image)],
+                vec![sort_by_date("$library", "%Y/%m")],
+            ),
+            rule(
+                "Sort videos into year/month folders",
+                vec![kind(FileKind::Video)],
+                vec![sort_by_date("$library/Videos", "%Y/%m")],
+            ),
+        ],
+    )]
+}
+
+fn screenshots() -> Vec<Folder> {
+    vec![folder(
+        "$desktop",
+        vec![
+            rule(
+                "Sort screenshots by month",
+                vec![name_contains("Screenshot"), extension("png,jpg")],
+                vec![sort_by_date("$screenshots", "%Y/%m")],
+            ),
+            rule(
+                "Move screen recordings",
+                vec![name_contains("Screen Recording"), extension("mov,mp4")],
+                vec![move_to("$screenshots/Recordings")],
+            ),
+        ],
+    )]
+}
+
+fn dev_scratch() -> Vec<Folder> {
+    vec![folder(
+        "$scratch",
+        vec![
+            rule(
+                "Delete logs and temporary files",
+                vec![extension("log,tmp,bak,swp")],
+                vec![Action::Delete],
+            ),
+            rule(
+                "Archive tarballs and zips",
+                vec![kind(FileKind::Archive)],
+                vec![move_to("$archive")],
+            ),
+            rule(
+                "Archive patches",
+                vec![extension("patch,diff")],
+                vec![move_to("$archive/patches")],
+            ),
+        ],
+    )]
+}
+
+fn invoices() -> Vec<Folder> {
+    vec![folder(
+        "$inbox",
+        vec![rule(
+            "File invoices and receipts by year",
+            vec![
+                extension("pdf"),
+                Condition::Any {
+                    conditions: ["invoice", "Invoice", "receipt", "Receipt"]
+                        .iter()
+                        .map(|value| name_contains(value))
+                        .collect(),
+                },
+            ],
+            vec![sort_by_date("$invoices", "%Y")],
+        )],
+    )]
+}
+
+fn example() -> Vec<Folder> {
+    let wallpapers = "test_folder/Pictures/Wallpapers";
+    vec![
+        folder(
+            "test_folder/Desktop",
+            vec![rule(
+                "Move all files to Downloads",
+                vec![Condition::Always],
+                vec![move_to("test_folder/Downloads")],
+            )],
+        ),
+        folder(
+            "test_folder/Downloads",
+            vec![
+                rule(
+                    "Move music files to Music folder",
+                    vec![kind(FileKind::Audio)],
+                    vec![move_to("test_folder/Music")],
+                ),
+                rule(
+                    "Move video files to Videos folder",
+                    vec![kind(FileKind::Video)],
+                    vec![move_to("test_folder/Videos")],
+                ),
+                rule(
+                    "Move document files to Documents folder",
+                    vec![kind(FileKind::Document)],
+                    vec![move_to("test_folder/Documents")],
+                ),
+                rule(
+                    "Move picture files to Pictures folder",
+                    vec![kind(FileKind::Image)],
+                    vec![move_to("test_folder/Pictures")],
+                ),
+            ],
+        ),
+        folder(
+            "test_folder/Pictures",
+            vec![
+                rule(
+                    "Move wallpapers to Wallpapers subfolder",
+                    vec![name_contains("wallpaper")],
+                    vec![move_to(wallpapers)],
+                ),
+                rule(
+                    "Move clearshots to Wallpapers subfolder",
+                    vec![name_contains("clearshot")],
+                    vec![move_to(wallpapers)],
+                ),
+                rule(
+                    "Move screenshots to Screenshots subfolder",
+                    vec![name_contains("screenshot")],
+                    vec![move_to("test_folder/Pictures/Screenshots")],
+                ),
+                rule(
+                    "Sort images into year/month subfolders",
+                    vec![kind(FileKind::Image)],
+                    vec![sort_by_date("test_folder/Pictures", "%Y/%b")],
+                ),
+            ],
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn wizard_fills_in_template_paths() {
+        let mut input = "2\n\n/data/library\n".as_bytes();
+        let mut output = Vec::new();
+
+        let (template, values) = wizard(&mut input, &mut output, BTreeMap::new()).unwrap();
+
+        assert_eq!(template.name, "photos");
+        assert_eq!(values["import"], "~/Pictures/Import");
+        assert_eq!(values["library"], "/data/library");
+    }
+
+    #[test]
+    fn every_template_loads() {
+        let dir = tempdir().unwrap();
+        for template in TEMPLATES {
+            let dest = destination(dir.path(), template);
+            config::write_rule_file(&dest, &template.rule_set(&BTreeMap::new())).unwrap();
+            config::load_config(&dest, None).unwrap();
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::config::FileKind;
+use std::path::Path;
+
+/// The MIME type of a file, from its first bytes when they are recognized and from its
+/// extension otherwise, so empty and plain-text files still get one.
+pub fn detect(path: &Path) -> Option<String> {
+    match infer::get_from_path(path) {
+        Ok(Some(sniffed)) => Some(sniffed.mime_type().to_string()),
+        _ => {
+            let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+            let mime = match extension.as_str() {
+                // Installers and disk images, which `mime_guess` only knows as raw bytes
+                // or, for `rpm`, as RealAudio.
+                "exe" | "dll" => "application/vnd.microsoft.portable-executable",
+                "msi" => "application/x-msi",
+                "dmg" => "application/x-apple-diskimage",
+                "pkg" => "application/vnd.apple.installer+xml",
+                "deb" => "application/vnd.debian.binary-package",
+                "rpm" => "application/x-rpm",
+                "appimage" => "application/x-executable",
+                "apk" => "application/vnd.android.package-archive",
+                _ => mime_guess::from_ext(&extension).first_raw()?,
+            };
+            Some(mime.to_string())
+        }
+    }
+}
+
+/// The kind group a MIME type belongs to, if any.
+pub fn kind_of(mime: &str) -> Option<FileKind> {
+    let (top, sub) = mime.split_once('/')?;
+    let kind = match top {
+        "image" => FileKind::Image,
+        "audio" => FileKind::Audio,
+        "video" => FileKind::Video,
+        "font" => FileKind::Font,
+        "text" if matches!(sub, "plain" | "markdown" | "csv" | "rtf") => FileKind::Document,
+        "application" => match sub {
+            "pdf" | "msword" | "rtf" | "postscript" | "epub+zip" | "x-mobipocket-ebook"
+            | "vnd.ms-excel" | "vnd.ms-powerpoint" => FileKind::Document,
+            _ if sub.starts_with("vnd.oasis.opendocument.")
+                || sub.starts_with("vnd.openxmlformats-officedocument.") =>
+            {
+                FileKind::Document
+            }
+            "zip"
+            | "gzip"
+            | "x-gzip"
+            | "x-tar"
+            | "x-gtar"
+            | "vnd.rar"
+            | "x-rar-compressed"
+            | "x-7z-compressed"
+            | "x-bzip"
+            | "x-bzip2"
+            | "vnd.bzip3"
+            | "x-xz"
+            | "x-lzip"
+            | "x-lz4"
+            | "zstd"
+            | "x-compress"
+            | "x-compressed"
+            | "x-cpio"
+            | "x-unix-archive"
+            | "vnd.ms-cab-compressed" => FileKind::Archive,
+            "x-executable"
+            | "x-mach-binary"
+            | "vnd.microsoft.portable-executable"
+            | "x-msdownload"
+            | "x-msdos-program"
+            | "x-msi"
+            | "x-apple-diskimage"
+            | "vnd.debian.binary-package"
+            | "x-debian-package"
+            | "x-rpm"
+            | "vnd.android.package-archive"
+            | "java-archive"
+            | "x-sh"
+            | "wasm" => FileKind::Executable,
+            "font-woff" | "font-sfnt" | "x-font-ttf" | "x-font-otf" | "vnd.ms-fontobject" => {
+                FileKind::Font
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn sniffs_content_before_trusting_the_extension() {
+        let dir = tempdir().unwrap();
+        let kind = |name: &str, content: &[u8]| {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            detect(&path).as_deref().and_then(kind_of)
+        };
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+        assert_eq!(kind("photo.JPG", png), Some(FileKind::Image));
+        assert_eq!(kind("download", png), Some(FileKind::Image));
+        assert_eq!(kind("notes.txt", png), Some(FileKind::Image));
+        assert_eq!(kind("song.mp3", b""), Some(FileKind::Audio));
+        assert_eq!(kind("notes.txt", b"hello"), Some(FileKind::Document));
+        assert_eq!(
+            kind("backup.tar.gz", b"\x1f\x8b\x08\0"),
+            Some(FileKind::Archive)
+        );
+        assert_eq!(kind("setup.exe", b"MZ\x90\0"), Some(FileKind::Executable));
+        assert_eq!(kind("tool.rpm", b""), Some(FileKind::Executable));
+        assert_eq!(kind("unknown", b"hello"), None);
+    }
+}
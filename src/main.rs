@@ -1,27 +1,39 @@
 mod actions;
+mod check;
 mod conditions;
 mod config;
-use crate::conditions::create_condition;
-use crate::config::{Action, FolderRule};
+mod defaults;
+mod graph;
+mod import;
+mod includes;
+mod init;
+mod kinds;
+mod migrate;
+mod paths;
+mod patterns;
+mod profiles;
+mod scan;
+mod schedule;
+mod units;
+use crate::conditions::{create_group, Condition, Entry};
+use crate::config::{Action, Folder, FolderRule, OnConflict, Rule};
+use crate::scan::Scanner;
 
+use chrono::Local;
 use clap::{App, Arg};
 use log::{error, info, warn};
 use notify::{recommended_watcher, RecursiveMode, Watcher};
 use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
-use std::collections::{HashMap, HashSet};
-use std::env;
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::path::Path;
-use std::sync::mpsc::channel;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::Duration;
 
 const MAX_MOVEMENTS: usize = 10;
 
-#[cfg(target_os = "macos")]
-static HOME: &str = env!("HOME");
-#[cfg(target_os = "windows")]
-static HOME: &str = env!("USERPROFILE");
-
 fn main() {
     SimpleLogger::init(LevelFilter::Info, LogConfig::default()).unwrap();
 
@@ -32,144 +44,617 @@ fn main() {
         .arg(Arg::new("init").short('i').long("init"))
         .arg(Arg::new("run").short('r').long("run"))
         .arg(Arg::new("watch").short('w').long("watch"))
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("FILE|DIR")
+                .help("Rule file, or directory of rule files to load")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::new("profile")
+                .short('p')
+                .long("profile")
+                .value_name("NAME")
+                .help("Profile to apply over the rule files")
+                .env("ORDERLY_PROFILE")
+                .takes_value(true)
+                .global(true),
+        )
+        .subcommand(
+            App::new("init")
+                .about("Creates a rule file from a template, asking for the folders to use")
+                .arg(
+                    Arg::new("template")
+                        .long("template")
+                        .value_name("NAME")
+                        .help("Template to use without asking any questions")
+                        .possible_values(init::TEMPLATES.iter().map(|t| t.name)),
+                )
+                .arg(
+                    Arg::new("var")
+                        .long("var")
+                        .value_name("NAME=PATH")
+                        .help("Sets one of the template's folders instead of its default")
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("Overwrite an existing rule file"),
+                ),
+        )
+        .subcommand(App::new("run").about("Organizes files once"))
+        .subcommand(App::new("watch").about("Organizes files whenever watched folders change"))
+        .subcommand(
+            App::new("check")
+                .about("Validates rule files and prints a JSON report")
+                .arg(
+                    Arg::new("strict")
+                        .long("strict")
+                        .help("Exit with an error on warnings too"),
+                ),
+        )
+        .subcommand(App::new("schema").about("Prints a JSON Schema for rule files"))
+        .subcommand(
+            App::new("graph")
+                .about("Prints how files flow between folders as a Graphviz or Mermaid graph")
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .possible_values(["dot", "mermaid"])
+                        .default_value("dot"),
+                ),
+        )
+        .subcommand(
+            App::new("import")
+                .about("Translates another tool's config into an Orderly rule file")
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .value_name("TOOL")
+                        .help("Tool the config was written for")
+                        .possible_values(["organize"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Rule file to write [default: <name>.yaml in the config directory]")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("FILE")
+                        .help("Config file to translate")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            App::new("migrate")
+                .about("Upgrades rule files to the current format version, keeping backups"),
+        )
         .get_matches();
 
+    let config_path = matches
+        .value_of("config")
+        .map(PathBuf::from)
+        .unwrap_or_else(paths::default_config_path);
+    let config_path = config_path.as_path();
+    let profile = matches.value_of("profile");
+
+    match matches.subcommand() {
+        Some(("init", sub_matches)) => {
+            return init_orderly(
+                config_path,
+                sub_matches.value_of("template"),
+                sub_matches.values_of("var").into_iter().flatten().collect(),
+                sub_matches.is_present("force"),
+            )
+        }
+        Some(("run", _)) => return run_orderly(config_path, profile),
+        Some(("watch", _)) => return watch_orderly(config_path, profile),
+        Some(("check", sub_matches)) => {
+            return check_orderly(config_path, profile, sub_matches.is_present("strict"))
+        }
+        Some(("schema", _)) => return schema_orderly(),
+        Some(("migrate", _)) => return migrate_orderly(config_path),
+        Some(("graph", sub_matches)) => {
+            let format = match sub_matches.value_of("format") {
+                Some("mermaid") => graph::Format::Mermaid,
+                _ => graph::Format::Dot,
+            };
+            return graph_orderly(config_path, profile, format);
+        }
+        Some(("import", sub_matches)) => {
+            let input = Path::new(sub_matches.value_of("FILE").unwrap());
+            let output = sub_matches.value_of("output").map(PathBuf::from);
+            return import_orderly(config_path, input, output);
+        }
+        _ => {}
+    }
+
     if matches.is_present("init") {
-        init_orderly();
+        // Scripts calling the old flag keep getting the example rules without questions.
+        let template = if io::stdin().is_terminal() {
+            None
+        } else {
+            Some("example")
+        };
+        init_orderly(config_path, template, Vec::new(), false);
     }
 
     if matches.is_present("run") {
-        run_orderly();
+        run_orderly(config_path, profile);
     }
 
     if matches.is_present("watch") {
-        watch_orderly();
+        watch_orderly(config_path, profile);
     }
 }
 
-fn init_orderly() {
+fn init_orderly(config_path: &Path, template: Option<&str>, vars: Vec<&str>, force: bool) {
     info!("Initializing Orderly...");
-    if let Err(e) = config::create_example_rule() {
-        error!("Failed to create example rule: {}", e);
+    let mut values = BTreeMap::new();
+    for var in vars {
+        match var.split_once('=') {
+            Some((name, path)) => values.insert(name.to_string(), path.to_string()),
+            None => {
+                error!("Expected NAME=PATH, got `{}`", var);
+                process::exit(1);
+            }
+        };
+    }
+
+    let (template, values) = match template.and_then(init::template) {
+        Some(template) => (template, values),
+        None if io::stdin().is_terminal() => {
+            match init::wizard(&mut io::stdin().lock(), &mut io::stdout(), values) {
+                Ok(answers) => answers,
+                Err(e) => {
+                    error!("Failed to read answers: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        None => {
+            error!("Not running in a terminal; choose a template with --template");
+            process::exit(1);
+        }
+    };
+    for name in values.keys() {
+        if !template.variable_names().any(|known| known == name) {
+            warn!("Template {} has no folder called `{}`", template.name, name);
+        }
+    }
+
+    let dest = init::destination(config_path, template);
+    if dest.exists() && !force {
+        error!(
+            "{} already exists; pass --force to overwrite it",
+            dest.display()
+        );
+        process::exit(1);
+    }
+    match config::write_rule_file(&dest, &template.rule_set(&values)) {
+        Ok(()) => info!("Wrote the {} template to {}", template.name, dest.display()),
+        Err(e) => error!("Failed to create rule file {}: {}", dest.display(), e),
     }
 }
 
-fn run_orderly() {
+fn check_orderly(config_path: &Path, profile: Option<&str>, strict: bool) {
+    let report = check::check(config_path, profile);
+    print_output(&serde_json::to_string_pretty(&report).unwrap());
+    if report.errors > 0 || (strict && report.warnings > 0) {
+        process::exit(1);
+    }
+}
+
+/// Writes command output to stdout, followed by a newline unless it ends with one. A
+/// reader that stops early, like `| head`, is not an error.
+fn print_output(output: &str) {
+    let mut stdout = io::stdout().lock();
+    let newline = if output.ends_with('\n') { "" } else { "\n" };
+    match write!(stdout, "{}{}", output, newline).and_then(|()| stdout.flush()) {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+            error!("Failed to write output: {}", e);
+            process::exit(1);
+        }
+        _ => {}
+    }
+}
+
+fn schema_orderly() {
+    let schema = schemars::schema_for!(Rule);
+    print_output(&serde_json::to_string_pretty(&schema).unwrap());
+}
+
+fn graph_orderly(config_path: &Path, profile: Option<&str>, format: graph::Format) {
+    match config::load_rules(config_path, profile) {
+        Ok(rule_sets) => print_output(&graph::Graph::build(&rule_sets).render(format)),
+        Err(e) => {
+            error!("Error loading config: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn import_orderly(config_path: &Path, input: &Path, output: Option<PathBuf>) {
+    let output = output.unwrap_or_else(|| {
+        let dir = if config::is_rule_file(config_path) {
+            config_path.parent().unwrap_or(Path::new(".")).to_path_buf()
+        } else {
+            config_path.to_path_buf()
+        };
+        let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+        dir.join(format!("{}.yaml", stem))
+    });
+    if output.exists() {
+        error!(
+            "{} already exists; choose another file with --output",
+            output.display()
+        );
+        process::exit(1);
+    }
+    match import::from_organize(input, &output) {
+        Ok(report) => print_output(&serde_json::to_string_pretty(&report).unwrap()),
+        Err(e) => {
+            error!("Failed to import {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn migrate_orderly(config_path: &Path) {
+    let files = match migrate::files(config_path) {
+        Ok(files) => files,
+        Err(e) => {
+            error!("Error loading config: {}", e);
+            process::exit(1);
+        }
+    };
+    let mut failed = false;
+    for (file, fragment) in &files {
+        match migrate::migrate_file(file, *fragment) {
+            Ok(migrate::Outcome::Current) => {
+                info!(
+                    "{} is already at version {}",
+                    file.display(),
+                    migrate::CURRENT_VERSION
+                )
+            }
+            Ok(migrate::Outcome::Migrated {
+                from,
+                backup,
+                comments_lost,
+            }) => {
+                info!(
+                    "Migrated {} from version {} to {}, backup saved as {}",
+                    file.display(),
+                    from,
+                    migrate::CURRENT_VERSION,
+                    backup.display()
+                );
+                if comments_lost {
+                    warn!(
+                        "Could not keep the comments in {}; they are still in the backup",
+                        file.display()
+                    );
+                }
+            }
+            Err(e) => {
+                error!("Failed to migrate {}", e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn run_orderly(config_path: &Path, profile: Option<&str>) {
+    if let Some(profile) = profile {
+        info!("Using profile {}", profile);
+    }
+    match config::load_rules(config_path, profile) {
+        Ok(rule_sets) => organize(&rule_sets),
+        Err(e) => error!("Error loading config: {}", e),
+    }
+}
+
+fn organize(rule_sets: &[Rule]) {
     info!("Running Orderly...");
     let mut processed_files = HashSet::new();
     let mut file_movements = HashMap::new();
     let mut ignored_rules = HashSet::new();
+    let now = Local::now();
 
-    match config::load_config("rules/example.yaml") {
-        Ok(config) => {
-            // info!("Config loaded: {:#?}", config);
-            for folder in config.folders {
-                for rule in &folder.rules {
-                    if !ignored_rules.contains(&rule.name) {
-                        if let Err(e) = handle_conditions(
-                            &folder.path,
-                            rule,
-                            &mut processed_files,
-                            &mut file_movements,
-                        ) {
-                            warn!("Ignoring rule '{}': {}", rule.name, e);
-                            log_error(&format!("Ignoring rule '{}': {}", rule.name, e));
-                            ignored_rules.insert(rule.name.clone());
-                        }
-                    }
+    for folder in rule_sets.iter().flat_map(|rule_set| &rule_set.folders) {
+        if let Some(reason) =
+            schedule::skip_reason(&folder.active_when, &folder.inactive_when, &now)
+        {
+            info!("Skipping folder {}: {}", folder.path, reason);
+            continue;
+        }
+        for rule in folder.rules_by_priority() {
+            if let Some(reason) =
+                schedule::skip_reason(&rule.active_when, &rule.inactive_when, &now)
+            {
+                info!("Skipping rule '{}': {}", rule.name, reason);
+                continue;
+            }
+            if !ignored_rules.contains(&rule.name) {
+                if let Err(e) =
+                    handle_conditions(folder, rule, &mut processed_files, &mut file_movements)
+                {
+                    warn!("Ignoring rule '{}': {}", rule.name, e);
+                    log_error(&format!("Ignoring rule '{}': {}", rule.name, e));
+                    ignored_rules.insert(rule.name.clone());
                 }
             }
         }
-        Err(e) => error!("Error loading config: {}", e),
     }
 }
 
-fn watch_orderly() {
+fn watch_orderly(config_path: &Path, profile: Option<&str>) {
+    // Watch events carry absolute paths, so compare rule files in that form.
+    let config_path = match config_path.canonicalize() {
+        Ok(path) => path,
+        Err(e) => {
+            error!("Error loading config: {}: {}", config_path.display(), e);
+            return;
+        }
+    };
+    let mut rule_sets = match config::load_rules(&config_path, profile) {
+        Ok(rule_sets) => rule_sets,
+        Err(e) => {
+            error!("Error loading config: {}", e);
+            return;
+        }
+    };
+
+    if let Some(profile) = profile {
+        info!("Using profile {}", profile);
+    }
     info!("Running initial organization...");
-    run_orderly(); // Perform the initial run
+    organize(&rule_sets);
 
     info!("Watching for changes...");
-    match config::load_config("rules/example.yaml") {
-        Ok(config) => {
-            let (tx, rx) = channel();
-            let mut watcher = recommended_watcher(move |res| tx.send(res).unwrap()).unwrap();
-
-            for folder in config.folders {
-                let path = Path::new(&folder.path);
-                watcher.watch(path, RecursiveMode::Recursive).unwrap();
-            }
+    let (tx, rx) = channel();
+    let mut watcher = recommended_watcher(move |res| tx.send(res).unwrap()).unwrap();
+    let mut watched = BTreeMap::new();
+    update_watches(
+        &mut watcher,
+        &mut watched,
+        watch_targets(&config_path, &rule_sets),
+    );
 
-            loop {
-                match rx.recv() {
-                    Ok(event) => match event {
-                        Ok(notify::Event { kind, paths, .. }) => {
-                            for path in paths {
-                                info!("File change detected: {:?}, {:?}", path, kind);
-                            }
-                            run_orderly();
-                        }
-                        Err(e) => error!("Watch error: {:?}", e),
-                    },
-                    Err(e) => error!("Watch error: {:?}", e),
+    while let Some(paths) = next_changes(&rx, WATCH_DEBOUNCE) {
+        let rules_changed = paths
+            .iter()
+            .any(|path| is_rule_file_change(path, &config_path, &rule_sets));
+        let scanned: Vec<_> = paths
+            .iter()
+            // Files that are gone, like the ones organizing just moved, need no work.
+            .filter(|path| path.exists() && is_scanned(path, &rule_sets))
+            .collect();
+        for path in paths
+            .iter()
+            .filter(|path| is_rule_file_change(path, &config_path, &rule_sets))
+            .chain(scanned.iter().copied())
+        {
+            info!("File change detected: {}", path.display());
+        }
+        if rules_changed {
+            match config::load_rules(&config_path, profile) {
+                Ok(new_rule_sets) => {
+                    info!("Rule files changed, reloaded config");
+                    let targets = watch_targets(&config_path, &new_rule_sets);
+                    update_watches(&mut watcher, &mut watched, targets);
+                    rule_sets = new_rule_sets;
+                }
+                Err(e) => {
+                    let msg = format!("Keeping previous config, reload failed: {}", e);
+                    error!("{}", msg);
+                    log_error(&msg);
+                    continue;
                 }
             }
         }
-        Err(e) => error!("Error loading config: {}", e),
+        if rules_changed || !scanned.is_empty() {
+            organize(&rule_sets);
+        }
     }
 }
 
+/// How long watch mode waits for events to stop before acting on them, so that a
+/// save made of several writes and renames is handled once.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Waits for the next change, then collects the paths of every event that follows
+/// until none arrives for `quiet`. Returns `None` once the watcher has gone away.
+fn next_changes(
+    rx: &Receiver<notify::Result<notify::Event>>,
+    quiet: Duration,
+) -> Option<BTreeSet<PathBuf>> {
+    let mut paths = BTreeSet::new();
+    let mut add = |event| match event {
+        Ok(notify::Event { paths: changed, .. }) => paths.extend(changed),
+        Err(e) => error!("Watch error: {:?}", e),
+    };
+    add(rx.recv().ok()?);
+    loop {
+        match rx.recv_timeout(quiet) {
+            Ok(event) => add(event),
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => return Some(paths),
+        }
+    }
+}
+
+/// Everything watch mode listens on: the watched folders, plus the rule files and
+/// the files they include, so edits to them are picked up.
+fn watch_targets(config_path: &Path, rule_sets: &[Rule]) -> BTreeMap<PathBuf, RecursiveMode> {
+    let mut targets = BTreeMap::new();
+    let mut add = |path: PathBuf, mode| {
+        let entry = targets.entry(path).or_insert(mode);
+        if mode == RecursiveMode::Recursive {
+            *entry = mode;
+        }
+    };
+
+    for folder in rule_sets.iter().flat_map(|rule_set| &rule_set.folders) {
+        let recursive = Scanner::new(folder).is_ok_and(|scanner| scanner.is_recursive());
+        let mode = if recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        add(PathBuf::from(&folder.path), mode);
+    }
+    if config_path.is_dir() {
+        add(config_path.to_path_buf(), RecursiveMode::Recursive);
+    }
+    // Editors often save by replacing the file, so watch the directory holding it.
+    let files = std::iter::once(config_path)
+        .filter(|path| !path.is_dir())
+        .chain(
+            rule_sets
+                .iter()
+                .flat_map(|r| r.included.iter().map(PathBuf::as_path)),
+        );
+    for file in files {
+        if let Some(parent) = file.parent() {
+            add(parent.to_path_buf(), RecursiveMode::NonRecursive);
+        }
+    }
+    targets
+}
+
+/// Adds and removes watches so that exactly `targets` are watched.
+fn update_watches(
+    watcher: &mut impl Watcher,
+    watched: &mut BTreeMap<PathBuf, RecursiveMode>,
+    targets: BTreeMap<PathBuf, RecursiveMode>,
+) {
+    for (path, mode) in watched.iter() {
+        if targets.get(path) != Some(mode) {
+            if let Err(e) = watcher.unwatch(path) {
+                warn!("Failed to stop watching {}: {}", path.display(), e);
+            }
+        }
+    }
+    watched.retain(|path, mode| targets.get(path) == Some(mode));
+
+    for (path, mode) in targets {
+        if watched.contains_key(&path) {
+            continue;
+        }
+        match watcher.watch(&path, mode) {
+            Ok(()) => {
+                info!("Watching {}", path.display());
+                watched.insert(path, mode);
+            }
+            Err(e) => {
+                let msg = format!("Failed to watch {}: {}", path.display(), e);
+                error!("{}", msg);
+                log_error(&msg);
+            }
+        }
+    }
+}
+
+/// Whether a changed path is one that some folder's scan would pick up.
+fn is_scanned(path: &Path, rule_sets: &[Rule]) -> bool {
+    rule_sets
+        .iter()
+        .flat_map(|rule_set| &rule_set.folders)
+        .filter_map(|folder| Scanner::new(folder).ok())
+        .any(|scanner| scanner.covers(path))
+}
+
+/// Whether a changed path is a rule file or one they include. Editor lock and backup
+/// files, like `.#rules.yaml`, don't count.
+fn is_rule_file_change(path: &Path, config_path: &Path, rule_sets: &[Rule]) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if !config::is_rule_file(path) || name.starts_with('.') || name.starts_with('#') {
+        return false;
+    }
+    path.starts_with(config_path)
+        || rule_sets
+            .iter()
+            .any(|rule_set| rule_set.included.iter().any(|included| included == path))
+}
+
 fn handle_conditions(
-    folder_path: &str,
+    folder_config: &Folder,
     rule: &FolderRule,
     processed_files: &mut HashSet<String>,
     file_movements: &mut HashMap<String, usize>,
 ) -> Result<(), String> {
-    let folder = Path::new(folder_path);
+    let folder = Path::new(&folder_config.path);
     if !folder.exists() {
         let msg = format!("Directory does not exist: {}", folder.display());
         error!("{}", msg);
         return Err(msg);
     }
 
-    let entries = folder.read_dir().map_err(|e| {
-        let msg = format!("Failed to read directory {}: {}", folder.display(), e);
-        error!("{}", msg);
-        msg
+    let entries = Scanner::new(folder_config)
+        .and_then(|scanner| scanner.entries())
+        .map_err(|e| {
+            error!("{}", e);
+            e
+        })?;
+
+    let match_type = rule.match_type.unwrap_or(folder_config.match_type);
+    let conditions = create_group(&rule.conditions, match_type).map_err(|e| {
+        error!("{}", e);
+        e
     })?;
 
-    for entry in entries {
-        if let Ok(entry) = entry {
-            let src_path = entry.path();
-            let src_path_str = src_path.to_str().unwrap().to_string();
+    for src_path in entries {
+        let src_path_str = src_path.to_str().unwrap().to_string();
 
-            if processed_files.contains(&src_path_str) {
-                info!("Skipping already processed file: {}", src_path.display());
-                continue;
-            }
+        if processed_files.contains(&src_path_str) {
+            info!("Skipping already processed file: {}", src_path.display());
+            continue;
+        }
 
-            for condition in &rule.conditions {
-                let cond = create_condition(&condition.condition_type, &condition.value);
-                if cond.evaluate(&src_path) {
-                    processed_files.insert(src_path_str.clone());
-
-                    let movement_count = file_movements.entry(src_path_str.clone()).or_insert(0);
-                    *movement_count += 1;
-
-                    if *movement_count > MAX_MOVEMENTS {
-                        let msg = format!(
-                            "Potential infinite loop detected for file: {}",
-                            src_path.display()
-                        );
-                        warn!("{}", msg);
-                        log_error(&msg);
-                        return Err(msg);
-                    }
-
-                    for action in &rule.actions {
-                        execute_action(&src_path, action, processed_files, file_movements);
-                    }
-                }
-            }
+        let entry = Entry::new(&src_path, folder);
+        if !conditions.evaluate(&entry) {
+            continue;
+        }
+        let captures = entry.into_captures();
+
+        if rule.stops() {
+            processed_files.insert(src_path_str.clone());
+        }
+
+        let movement_count = file_movements.entry(src_path_str.clone()).or_insert(0);
+        *movement_count += 1;
+
+        if *movement_count > MAX_MOVEMENTS {
+            let msg = format!(
+                "Potential infinite loop detected for file: {}",
+                src_path.display()
+            );
+            warn!("{}", msg);
+            log_error(&msg);
+            return Err(msg);
+        }
+
+        for action in &rule.actions {
+            execute_action(
+                &src_path,
+                action,
+                &captures,
+                processed_files,
+                file_movements,
+            );
         }
     }
     Ok(())
@@ -178,15 +663,51 @@ fn handle_conditions(
 fn execute_action(
     src_path: &Path,
     action: &Action,
+    captures: &BTreeMap<String, String>,
     processed_files: &mut HashSet<String>,
     file_movements: &mut HashMap<String, usize>,
 ) {
-    match action.action_type.as_str() {
-        "delete" => handle_delete(src_path),
-        "move" => handle_move(src_path, action, processed_files, file_movements),
-        "copy" => handle_copy(src_path, action, processed_files, file_movements),
-        "sort_by_date" => handle_sort_by_date(src_path, action, processed_files),
-        _ => log_error(&format!("Unknown action type: {}", action.action_type)),
+    let filled = match action {
+        Action::Move { path, .. } | Action::Copy { path, .. } | Action::SortByDate { path, .. } => {
+            patterns::fill(path, captures)
+        }
+        Action::Delete | Action::Continue => Ok(String::new()),
+    };
+    let path = match filled {
+        Ok(path) => path,
+        Err(e) => {
+            log_error(&format!("Skipping action on {}: {}", src_path.display(), e));
+            return;
+        }
+    };
+    match action {
+        Action::Delete => handle_delete(src_path),
+        Action::Continue => {}
+        Action::Move { on_conflict, .. } => handle_move(
+            src_path,
+            &path,
+            on_conflict.unwrap_or_default(),
+            processed_files,
+            file_movements,
+        ),
+        Action::Copy { on_conflict, .. } => handle_copy(
+            src_path,
+            &path,
+            on_conflict.unwrap_or_default(),
+            processed_files,
+            file_movements,
+        ),
+        Action::SortByDate {
+            pattern,
+            on_conflict,
+            ..
+        } => handle_sort_by_date(
+            src_path,
+            &path,
+            pattern.as_deref().unwrap_or(config::DEFAULT_SORT_PATTERN),
+            on_conflict.unwrap_or_default(),
+            processed_files,
+        ),
     }
 }
 
@@ -199,68 +720,280 @@ fn handle_delete(src_path: &Path) {
 
 fn handle_move(
     src_path: &Path,
-    action: &Action,
+    dest_path: &str,
+    on_conflict: OnConflict,
     processed_files: &mut HashSet<String>,
     file_movements: &mut HashMap<String, usize>,
 ) {
-    let dest_path = action.path.as_ref().unwrap().replace("~", &HOME);
-
     info!("Moving file from {} to {}", src_path.display(), dest_path);
-    if let Err(e) = actions::move_file(src_path.to_str().unwrap(), &dest_path) {
+    if let Err(e) = actions::move_file(src_path.to_str().unwrap(), dest_path, on_conflict) {
         log_error(&format!("Failed to move file: {}", e));
     } else {
-        processed_files.insert(dest_path.clone());
-        let dest_path_str = dest_path.to_string();
-        let movement_count = file_movements.entry(dest_path_str).or_insert(0);
+        processed_files.insert(dest_path.to_string());
+        let movement_count = file_movements.entry(dest_path.to_string()).or_insert(0);
         *movement_count += 1;
     }
 }
 
 fn handle_copy(
     src_path: &Path,
-    action: &Action,
+    dest_path: &str,
+    on_conflict: OnConflict,
     processed_files: &mut HashSet<String>,
     file_movements: &mut HashMap<String, usize>,
 ) {
-    let dest_path = action
-        .path
-        .as_ref()
-        .unwrap()
-        .replace("~", &env::var("HOME").unwrap());
     info!("Copying file from {} to {}", src_path.display(), dest_path);
-    if let Err(e) = actions::copy_file(src_path.to_str().unwrap(), &dest_path) {
+    if let Err(e) = actions::copy_file(src_path.to_str().unwrap(), dest_path, on_conflict) {
         log_error(&format!("Failed to copy file: {}", e));
     } else {
-        processed_files.insert(dest_path.clone());
-        let dest_path_str = dest_path.to_string();
-        let movement_count = file_movements.entry(dest_path_str).or_insert(0);
+        processed_files.insert(dest_path.to_string());
+        let movement_count = file_movements.entry(dest_path.to_string()).or_insert(0);
         *movement_count += 1;
     }
 }
 
-fn handle_sort_by_date(src_path: &Path, action: &Action, processed_files: &mut HashSet<String>) {
-    let base_path = action.path.as_ref().unwrap().replace("~", &HOME);
-
-    let pattern = action.pattern.as_ref().unwrap();
+fn handle_sort_by_date(
+    src_path: &Path,
+    base_path: &str,
+    pattern: &str,
+    on_conflict: OnConflict,
+    processed_files: &mut HashSet<String>,
+) {
     info!(
         "Sorting file by date from {} to {}",
         src_path.display(),
         base_path
     );
-    if let Err(e) = actions::sort_file_by_date(src_path.to_str().unwrap(), &base_path, pattern) {
+    if let Err(e) =
+        actions::sort_file_by_date(src_path.to_str().unwrap(), base_path, pattern, on_conflict)
+    {
         log_error(&format!("Failed to sort file by date: {}", e));
     } else {
-        let dest_path = Path::new(&base_path).join(src_path.file_name().unwrap());
+        let dest_path = Path::new(base_path).join(src_path.file_name().unwrap());
         processed_files.insert(dest_path.to_str().unwrap().to_string());
     }
 }
 
 fn log_error(message: &str) {
-    let log_file_path = "error.log";
+    let log_file_path = paths::error_log_path();
+    if let Some(parent) = log_file_path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
     let mut file = OpenOptions::new()
         .append(true)
         .create(true)
-        .open(log_file_path)
+        .open(&log_file_path)
         .unwrap();
     writeln!(file, "{}", message).unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MatchType;
+    use tempfile::tempdir;
+
+    /// Runs a rule moving files with the given extensions into `out`, returning what moved.
+    fn moved(
+        folder_match: MatchType,
+        rule_match: Option<MatchType>,
+        extensions: &[&str],
+    ) -> Vec<String> {
+        let dir = tempdir().unwrap();
+        let inbox = dir.path().join("in");
+        fs::create_dir_all(inbox.join("sub")).unwrap();
+        fs::write(inbox.join("a.pdf"), "").unwrap();
+        fs::write(inbox.join("b.txt"), "").unwrap();
+        let out = dir.path().join("out");
+        let folder = Folder {
+            path: inbox.to_str().unwrap().into(),
+            match_type: folder_match,
+            ..Folder::default()
+        };
+        let rule = FolderRule {
+            match_type: rule_match,
+            conditions: extensions
+                .iter()
+                .map(|ext| config::Condition::Extension {
+                    value: vec![ext.to_string()],
+                })
+                .collect(),
+            actions: vec![Action::Move {
+                path: out.to_str().unwrap().into(),
+                on_conflict: None,
+            }],
+            ..FolderRule::default()
+        };
+
+        handle_conditions(&folder, &rule, &mut HashSet::new(), &mut HashMap::new()).unwrap();
+
+        assert!(inbox.join("sub").is_dir());
+        let mut names: Vec<_> = fs::read_dir(&out)
+            .map(|entries| {
+                entries
+                    .map(|e| e.unwrap().file_name().into_string().unwrap())
+                    .collect()
+            })
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn match_types_combine_conditions_and_never_match_folders() {
+        assert_eq!(moved(MatchType::All, None, &["pdf"]), ["a.pdf"]);
+        assert_eq!(moved(MatchType::All, None, &[]), ["a.pdf", "b.txt"]);
+        assert!(moved(MatchType::All, None, &["pdf", "txt"]).is_empty());
+        assert_eq!(
+            moved(MatchType::Any, None, &["pdf", "txt"]),
+            ["a.pdf", "b.txt"]
+        );
+        assert!(moved(MatchType::Any, None, &[]).is_empty());
+        assert_eq!(moved(MatchType::None, None, &["pdf"]), ["b.txt"]);
+        assert_eq!(moved(MatchType::None, None, &[]), ["a.pdf", "b.txt"]);
+        assert_eq!(
+            moved(MatchType::Any, Some(MatchType::None), &["pdf"]),
+            ["b.txt"]
+        );
+        assert_eq!(
+            moved(MatchType::None, Some(MatchType::All), &["pdf"]),
+            ["a.pdf"]
+        );
+    }
+
+    /// Runs two PDF rules copying into `low` and `high`, returning where the PDF landed.
+    fn copies(high: &str) -> Vec<&'static str> {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("in")).unwrap();
+        fs::write(dir.path().join("in/a.pdf"), "").unwrap();
+        let path = dir.path().join("rules.yaml");
+        fs::write(
+            &path,
+            format!(
+                "name: x\ndescription: y\nfolders:\n- path: in\n  rules:\n  - name: Low\n    conditions: [{{condition_type: extension, value: [pdf]}}]\n    actions: [{{action_type: copy, path: low}}]\n  - name: High\n    conditions: [{{condition_type: extension, value: [pdf]}}]\n{}",
+                high
+            ),
+        )
+        .unwrap();
+        let rule_set = config::load_config(&path, None).unwrap();
+        let names: Vec<_> = rule_set.folders[0]
+            .rules_by_priority()
+            .iter()
+            .map(|rule| rule.name.as_str())
+            .collect();
+        assert_eq!(
+            names[0],
+            if high.contains("priority") {
+                "High"
+            } else {
+                "Low"
+            }
+        );
+
+        organize(&[rule_set]);
+
+        ["low", "high"]
+            .iter()
+            .copied()
+            .filter(|dest| dir.path().join(dest).join("a.pdf").exists())
+            .collect()
+    }
+
+    #[test]
+    fn higher_priority_rules_run_first_and_stop_unless_told_to_continue() {
+        let copy = "    actions: [{action_type: copy, path: high}]\n";
+        assert_eq!(copies(copy), ["low"]);
+        assert_eq!(copies(&format!("    priority: 10\n{}", copy)), ["high"]);
+        assert_eq!(
+            copies(&format!("    priority: 10\n    stop: false\n{}", copy)),
+            ["low", "high"]
+        );
+        assert_eq!(
+            copies("    priority: 10\n    actions: [{action_type: copy, path: high}, {action_type: continue}]\n"),
+            ["low", "high"]
+        );
+    }
+
+    #[test]
+    fn watch_reacts_only_to_rule_files_and_scanned_entries() {
+        let dir = tempdir().unwrap();
+        let rules = dir.path().join("rules");
+        let inbox = dir.path().join("in");
+        let rule_set = Rule {
+            folders: vec![Folder {
+                path: inbox.to_str().unwrap().into(),
+                exclude: vec!["*.part".into()],
+                ..Folder::default()
+            }],
+            included: vec![dir.path().join("shared/media.yaml")],
+            ..serde_yaml::from_str("{version: 2, name: x, description: y, folders: []}").unwrap()
+        };
+        let rule_sets = [rule_set];
+        let rule_change =
+            |path: &str| is_rule_file_change(&dir.path().join(path), &rules, &rule_sets);
+
+        assert!(rule_change("rules/downloads.yaml"));
+        assert!(rule_change("rules/nested/photos.toml"));
+        assert!(rule_change("shared/media.yaml"));
+        assert!(!rule_change("shared/other.yaml"));
+        assert!(!rule_change("rules/sedAbc123"));
+        assert!(!rule_change("rules/.#downloads.yaml"));
+        assert!(!rule_change("rules/downloads.yaml~"));
+        assert!(!rule_change("rules/notes.txt"));
+        assert!(is_scanned(&inbox.join("a.pdf"), &rule_sets));
+        assert!(!is_scanned(&inbox.join("a.pdf.part"), &rule_sets));
+        assert!(!is_scanned(&inbox.join("sub/a.pdf"), &rule_sets));
+        assert!(!is_scanned(&dir.path().join("out/a.pdf"), &rule_sets));
+    }
+
+    #[test]
+    fn folders_are_watched_as_deep_as_they_are_scanned() {
+        let dir = tempdir().unwrap();
+        let folder = |name: &str, max_depth| Folder {
+            path: dir.path().join(name).to_str().unwrap().into(),
+            max_depth,
+            ..Folder::default()
+        };
+        let rule_set = Rule {
+            folders: vec![
+                folder("flat", Some(0)),
+                folder("one", Some(1)),
+                folder("all", None),
+            ],
+            ..serde_yaml::from_str("{version: 2, name: x, description: y, folders: []}").unwrap()
+        };
+
+        let targets = watch_targets(&dir.path().join("rules.yaml"), &[rule_set]);
+
+        assert_eq!(
+            targets[&dir.path().join("flat")],
+            RecursiveMode::NonRecursive
+        );
+        assert_eq!(targets[&dir.path().join("one")], RecursiveMode::Recursive);
+        assert_eq!(
+            targets[&dir.path().join("all")],
+            RecursiveMode::NonRecursive
+        );
+    }
+
+    #[test]
+    fn bursts_of_events_are_handled_once() {
+        let (tx, rx) = channel();
+        let event = |path: &str| {
+            Ok(notify::Event::new(notify::EventKind::Any).add_path(PathBuf::from(path)))
+        };
+        for path in ["rules/sedAbc123", "rules/a.yaml", "rules/a.yaml"] {
+            tx.send(event(path)).unwrap();
+        }
+
+        let paths = next_changes(&rx, Duration::from_millis(10)).unwrap();
+        tx.send(event("in/b.pdf")).unwrap();
+        let later = next_changes(&rx, Duration::from_millis(10)).unwrap();
+        drop(tx);
+
+        let paths: Vec<_> = paths.iter().map(|p| p.to_str().unwrap()).collect();
+        assert_eq!(paths, ["rules/a.yaml", "rules/sedAbc123"]);
+        assert_eq!(later.len(), 1);
+        assert!(next_changes(&rx, Duration::from_millis(10)).is_none());
+    }
+}
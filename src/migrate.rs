@@ -0,0 +1,510 @@
+use crate::config::{self, ConfigError, Format, Rule};
+use crate::includes;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The rule file format version this build reads and writes.
+pub const CURRENT_VERSION: u32 = 2;
+
+/// A change to the rule file format, applied to files older than `version`.
+struct Migration {
+    version: u32,
+    /// Upgrades a parsed document.
+    upgrade: fn(&mut Value),
+    /// Upgrades the file's lines in place so comments survive; `None` when it can't.
+    rewrite: fn(Format, &mut [String]) -> Option<()>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    // `extension` conditions take a list instead of a comma-separated string.
+    Migration {
+        version: 2,
+        upgrade: extension_lists,
+        rewrite: rewrite_extension_lists,
+    },
+    // Actions no longer carry the unused `path` and `pattern` keys as `null`.
+    Migration {
+        version: 2,
+        upgrade: null_action_keys,
+        rewrite: rewrite_null_action_keys,
+    },
+];
+
+/// What `migrate_file` did to a rule file.
+pub enum Outcome {
+    /// The file is already at the current version and was left alone.
+    Current,
+    Migrated {
+        from: u32,
+        backup: PathBuf,
+        /// Whether the file had to be rewritten from scratch, dropping its comments.
+        comments_lost: bool,
+    },
+}
+
+/// The format version a parsed rule file was written for; files without one are version 1.
+pub fn version_of(document: &Value) -> Result<u32, String> {
+    let version = match document.get("version") {
+        Some(version) => version,
+        None => return Ok(1),
+    };
+    match version.as_u64() {
+        Some(v) if (1..=u64::from(CURRENT_VERSION)).contains(&v) => Ok(v as u32),
+        Some(v) if v > u64::from(CURRENT_VERSION) => Err(format!(
+            "rule file version {} is newer than this orderly supports ({})",
+            v, CURRENT_VERSION
+        )),
+        _ => Err(format!(
+            "`version` must be a whole number from 1 to {}",
+            CURRENT_VERSION
+        )),
+    }
+}
+
+/// The format version of a rule file on disk, if it can be read.
+pub fn file_version(path: &Path) -> Option<u32> {
+    let content = fs::read_to_string(path).ok()?;
+    let document: Value = config::parse_content(path, &content).ok()?;
+    version_of(&document).ok()
+}
+
+/// Applies every migration newer than `from` and stamps the current version.
+pub fn upgrade(document: &mut Value, from: u32) {
+    for migration in MIGRATIONS.iter().filter(|m| m.version > from) {
+        (migration.upgrade)(document);
+    }
+    if let Value::Object(map) = document {
+        let rest = std::mem::take(map);
+        map.insert("version".into(), CURRENT_VERSION.into());
+        map.extend(rest.into_iter().filter(|(key, _)| key != "version"));
+    }
+}
+
+/// The rule files under `config_path` plus every file they include, which `migrate`
+/// upgrades, each with whether another file includes it.
+pub fn files(config_path: &Path) -> Result<Vec<(PathBuf, bool)>, ConfigError> {
+    let files = config::rule_files(config_path)?;
+    let mut seen: Vec<PathBuf> = files
+        .iter()
+        .map(|file| file.canonicalize().unwrap_or_else(|_| file.clone()))
+        .collect();
+    let mut included = Vec::new();
+    for file in &files {
+        for path in includes::declared_includes(file) {
+            if !included.contains(&path) {
+                included.push(path);
+            }
+        }
+    }
+    let mut listed: Vec<_> = files
+        .into_iter()
+        .zip(&seen)
+        .map(|(file, canonical)| (file, included.contains(canonical)))
+        .collect();
+    for path in included {
+        if !seen.contains(&path) {
+            seen.push(path.clone());
+            listed.push((path, true));
+        }
+    }
+    Ok(listed)
+}
+
+/// Upgrades a rule file to the current version in place, keeping the original as `<file>.bak`.
+///
+/// The text is edited where possible so comments and layout are kept, falling back to
+/// writing out the upgraded document. Nothing is written unless the upgraded file loads,
+/// as a rule set or, for a file other files include, as a fragment.
+pub fn migrate_file(path: &Path, fragment: bool) -> Result<Outcome, ConfigError> {
+    let content = fs::read_to_string(path).map_err(|e| ConfigError::new(path, e))?;
+    let mut document: Value = config::parse_content(path, &content)?;
+    let from = version_of(&document).map_err(|e| ConfigError::new(path, e))?;
+    if from == CURRENT_VERSION {
+        return Ok(Outcome::Current);
+    }
+    upgrade(&mut document, from);
+
+    let format = Format::of(path);
+    let edited = upgraded_text(path, &content, from, &document).map(|(text, _)| text);
+    let comments_lost = edited.is_none() && format != Format::Json && content.contains('#');
+    let output = match edited {
+        Some(text) => text,
+        None => config::serialize(format, &document).map_err(|e| ConfigError::new(path, e))?,
+    };
+    let loads = if fragment {
+        includes::parse_fragment(path, &output)
+    } else {
+        config::parse_content::<Rule>(path, &output).map(|_| ())
+    };
+    loads.map_err(|e| ConfigError {
+        message: format!("the upgraded file would not load: {}", e.message),
+        ..e
+    })?;
+
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    let backup = PathBuf::from(backup);
+    fs::copy(path, &backup).map_err(|e| ConfigError::new(&backup, e))?;
+    fs::write(path, output).map_err(|e| ConfigError::new(path, e))?;
+    Ok(Outcome::Migrated {
+        from,
+        backup,
+        comments_lost,
+    })
+}
+
+/// The text of an older rule file edited to read as the upgraded `document`, keeping
+/// comments and layout, along with the line of the original file each line came from.
+pub fn upgraded_text(
+    path: &Path,
+    content: &str,
+    from: u32,
+    document: &Value,
+) -> Option<(String, Vec<usize>)> {
+    let format = Format::of(path);
+    if format == Format::Json {
+        return None;
+    }
+    let original: Vec<String> = content.lines().map(String::from).collect();
+    let mut lines = original.clone();
+    for migration in MIGRATIONS.iter().filter(|m| m.version > from) {
+        (migration.rewrite)(format, &mut lines)?;
+    }
+    // Migrations remove a line by emptying it.
+    let (mut lines, mut origins): (Vec<String>, Vec<usize>) = lines
+        .into_iter()
+        .zip(&original)
+        .enumerate()
+        .filter(|(_, (line, old))| !line.is_empty() || old.is_empty())
+        .map(|(i, (line, _))| (line, i + 1))
+        .unzip();
+    if let Some(at) = set_version(format, &mut lines) {
+        let origin = origins.get(at).copied().unwrap_or(original.len() + 1);
+        origins.insert(at, origin);
+    }
+    let text = lines.join("\n") + "\n";
+    match config::parse_content::<Value>(path, &text) {
+        Ok(value) if value == *document => Some((text, origins)),
+        _ => None,
+    }
+}
+
+/// Replaces a top-level `version` key, or adds one above the first entry and returns
+/// its index.
+fn set_version(format: Format, lines: &mut Vec<String>) -> Option<usize> {
+    let version = match format {
+        Format::Toml => format!("version = {}", CURRENT_VERSION),
+        _ => format!("version: {}", CURRENT_VERSION),
+    };
+    let mut first_entry = None;
+    for (i, line) in lines.iter_mut().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if format == Format::Yaml && (trimmed == "---" || trimmed.starts_with('%')) {
+            continue;
+        }
+        if format == Format::Toml && trimmed.starts_with('[') {
+            break;
+        }
+        first_entry.get_or_insert(i);
+        match key_line(format, line) {
+            Some((column, "version", _)) if format == Format::Toml || column == 0 => {
+                *line = version;
+                return None;
+            }
+            _ => {}
+        }
+    }
+    let at = first_entry.unwrap_or(lines.len());
+    lines.insert(at, version);
+    Some(at)
+}
+
+fn extension_lists(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            if map.get("condition_type").and_then(Value::as_str) == Some("extension") {
+                if let Some(Value::String(list)) = map.get("value") {
+                    let list = split_list(list).into();
+                    map.insert("value".into(), list);
+                }
+            }
+            map.values_mut().for_each(extension_lists);
+        }
+        Value::Array(items) => items.iter_mut().for_each(extension_lists),
+        _ => {}
+    }
+}
+
+fn rewrite_extension_lists(format: Format, lines: &mut [String]) -> Option<()> {
+    for i in 0..lines.len() {
+        let column = match key_line(format, &lines[i]) {
+            Some((column, "condition_type", start)) => {
+                match string_scalar(format, &lines[i][start..]) {
+                    Some((kind, _)) if kind == "extension" => column,
+                    _ => continue,
+                }
+            }
+            _ => continue,
+        };
+        let j = match sibling(format, lines, i, column, "value") {
+            Some(j) => j,
+            None => continue,
+        };
+        let (_, _, start) = key_line(format, &lines[j])?;
+        // Values that aren't a single string are left for the check after rewriting.
+        let (list, comment) = match string_scalar(format, &lines[j][start..]) {
+            Some(scalar) => scalar,
+            None => continue,
+        };
+        let items: Vec<String> = split_list(&list)
+            .iter()
+            .map(|item| render_item(format, item))
+            .collect();
+        let mut line = format!("{}[{}]", &lines[j][..start], items.join(", "));
+        if !comment.is_empty() {
+            line = format!("{} {}", line, comment);
+        }
+        lines[j] = line;
+    }
+    Some(())
+}
+
+fn null_action_keys(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            if map.contains_key("action_type") {
+                map.retain(|key, value| !(is_unused_action_key(key) && value.is_null()));
+            }
+            map.values_mut().for_each(null_action_keys);
+        }
+        Value::Array(items) => items.iter_mut().for_each(null_action_keys),
+        _ => {}
+    }
+}
+
+fn is_unused_action_key(key: &str) -> bool {
+    key == "path" || key == "pattern"
+}
+
+fn rewrite_null_action_keys(format: Format, lines: &mut [String]) -> Option<()> {
+    // TOML has no null, so there is nothing to remove.
+    if format == Format::Toml {
+        return Some(());
+    }
+    for i in 0..lines.len() {
+        let column = match key_line(format, &lines[i]) {
+            Some((column, "action_type", _)) => column,
+            _ => continue,
+        };
+        for key in ["path", "pattern"] {
+            let j = match sibling(format, lines, i, column, key) {
+                Some(j) => j,
+                None => continue,
+            };
+            let (_, _, start) = key_line(format, &lines[j])?;
+            let value = lines[j][start..].split(" #").next().unwrap_or("").trim();
+            if !["", "~", "null", "Null", "NULL"].contains(&value) {
+                continue;
+            }
+            // Dropping the line that opens a sequence item would change the structure.
+            if lines[j].trim_start().starts_with("- ") {
+                return None;
+            }
+            lines[j].clear();
+        }
+    }
+    Some(())
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn render_item(format: Format, item: &str) -> String {
+    let plain = item.chars().any(|c| c.is_ascii_alphabetic())
+        && item
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c))
+        && !["true", "false", "null"].contains(&item);
+    if format == Format::Yaml && plain {
+        item.to_string()
+    } else {
+        serde_json::to_string(item).unwrap()
+    }
+}
+
+/// Parses a `key: value` (YAML) or `key = value` (TOML) line into the key's column, the
+/// key, and the byte offset where the value starts.
+fn key_line(format: Format, line: &str) -> Option<(usize, &str, usize)> {
+    let trimmed = line.trim_start();
+    let rest = match format {
+        Format::Yaml => trimmed.strip_prefix("- ").map_or(trimmed, str::trim_start),
+        _ => trimmed,
+    };
+    let column = line.len() - rest.len();
+    let separator = if format == Format::Yaml { ':' } else { '=' };
+    let (key, value) = rest.split_once(separator)?;
+    let key = key.trim_end();
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+    if format == Format::Yaml && !(value.is_empty() || value.starts_with(' ')) {
+        return None;
+    }
+    Some((column, key, line.len() - value.trim_start().len()))
+}
+
+/// Reads a quoted or (in YAML) plain string value, returning it and any trailing comment.
+fn string_scalar(format: Format, raw: &str) -> Option<(String, &str)> {
+    let raw = raw.trim_end();
+    let (text, rest) = match raw.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let end = raw[1..].find(quote)? + 1;
+            let text = &raw[1..end];
+            if text.contains('\\') {
+                return None;
+            }
+            (text, raw[end + 1..].trim_start())
+        }
+        c if format == Format::Yaml && !"[{|>&*!%@`#".contains(c) => match raw.find(" #") {
+            Some(i) => (raw[..i].trim_end(), &raw[i + 1..]),
+            None => (raw, ""),
+        },
+        _ => return None,
+    };
+    if !(rest.is_empty() || rest.starts_with('#')) {
+        return None;
+    }
+    Some((text.to_string(), rest))
+}
+
+/// Finds the line holding `key` in the same YAML mapping or TOML table as line `at`.
+fn sibling(format: Format, lines: &[String], at: usize, column: usize, key: &str) -> Option<usize> {
+    let is_key = |line: &String| matches!(key_line(format, line), Some((c, k, _)) if k == key && (format == Format::Toml || c == column));
+    let skip = |line: &String| {
+        let trimmed = line.trim();
+        trimmed.is_empty() || trimmed.starts_with('#')
+    };
+    let indent = |line: &String| line.len() - line.trim_start().len();
+
+    if format == Format::Toml {
+        let in_table = |line: &&String| !line.trim_start().starts_with('[');
+        let after = lines[at + 1..].iter().take_while(in_table);
+        if let Some(offset) = after.clone().position(is_key) {
+            return Some(at + 1 + offset);
+        }
+        let before = lines[..at].iter().rev().take_while(in_table);
+        return before
+            .clone()
+            .position(is_key)
+            .map(|offset| at - 1 - offset);
+    }
+
+    for (j, line) in lines.iter().enumerate().skip(at + 1) {
+        if skip(line) {
+            continue;
+        }
+        if indent(line) < column {
+            break;
+        }
+        if is_key(line) && indent(line) == column {
+            return Some(j);
+        }
+    }
+    // A key that opens a sequence item has no siblings above it.
+    if indent(&lines[at]) < column {
+        return None;
+    }
+    for j in (0..at).rev() {
+        let line = &lines[j];
+        if skip(line) {
+            continue;
+        }
+        if is_key(line) {
+            return Some(j);
+        }
+        if indent(line) < column {
+            break;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn migrates_yaml_in_place_keeping_comments() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rules.yaml");
+        let original = "# Downloads cleanup\nname: Downloads\ndescription: test\nfolders:\n- path: ~/Downloads\n  rules:\n  - name: Music\n    conditions:\n    - value: 'mp3, flac' # audio\n      condition_type: extension\n    - condition_type: extension\n      value: ogg\n    actions:\n    - action_type: delete\n";
+        fs::write(&path, original).unwrap();
+
+        let outcome = migrate_file(&path, false).unwrap();
+
+        assert!(matches!(
+            outcome,
+            Outcome::Migrated {
+                from: 1,
+                comments_lost: false,
+                ..
+            }
+        ));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# Downloads cleanup\nversion: 2\nname: Downloads\ndescription: test\nfolders:\n- path: ~/Downloads\n  rules:\n  - name: Music\n    conditions:\n    - value: [mp3, flac] # audio\n      condition_type: extension\n    - condition_type: extension\n      value: [ogg]\n    actions:\n    - action_type: delete\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("rules.yaml.bak")).unwrap(),
+            original
+        );
+        assert!(matches!(
+            migrate_file(&path, false).unwrap(),
+            Outcome::Current
+        ));
+    }
+
+    #[test]
+    fn migrated_files_load_and_broken_ones_are_left_alone() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("example.yaml");
+        let original = "name: Orderly Sandbox Rules\ndescription: d\nfolders:\n- path: Desktop\n  match_type: all\n  rules:\n  - name: Move all files to Downloads\n    conditions:\n    - condition_type: always\n      value: ''\n    actions:\n    - action_type: move\n      path: Downloads # keep\n      pattern: null\n";
+        fs::write(&path, original).unwrap();
+        let broken = dir.path().join("broken.yaml");
+        let typo = original.replace("action_type: move", "action_type: mvoe");
+        fs::write(&broken, &typo).unwrap();
+
+        migrate_file(&path, false).unwrap();
+        let err = migrate_file(&broken, false).err().unwrap();
+
+        let migrated = fs::read_to_string(&path).unwrap();
+        assert!(migrated.contains("      path: Downloads # keep\n"));
+        assert!(!migrated.contains("pattern"));
+        config::load_config(&path, None).unwrap();
+        assert!(
+            err.message
+                .starts_with("the upgraded file would not load: "),
+            "{}",
+            err
+        );
+        assert!(err.message.contains("unknown variant `mvoe`"), "{}", err);
+        assert_eq!(fs::read_to_string(&broken).unwrap(), typo);
+        assert!(!dir.path().join("broken.yaml.bak").exists());
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let document = serde_json::json!({ "version": CURRENT_VERSION + 1 });
+        assert!(version_of(&document).unwrap_err().contains("newer"));
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// The current user's home directory, resolved at runtime.
+pub fn home_dir() -> Option<PathBuf> {
+    dirs::home_dir()
+}
+
+/// Per-user configuration directory, e.g. `$XDG_CONFIG_HOME/orderly` on Linux.
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("orderly"))
+}
+
+/// Where Orderly keeps its state: `$ORDERLY_STATE_DIR` if set, otherwise the per-user
+/// state directory, e.g. `$XDG_STATE_HOME/orderly` on Linux. Platforms without a state
+/// directory use their local data directory instead.
+pub fn state_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("ORDERLY_STATE_DIR") {
+        return PathBuf::from(dir);
+    }
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|dir| dir.join("orderly"))
+        .unwrap_or_else(|| env::temp_dir().join("orderly"))
+}
+
+/// Rules used when `--config` isn't given: a `rules` directory in the current
+/// directory if there is one, otherwise the per-user configuration directory.
+pub fn default_config_path() -> PathBuf {
+    let local = Path::new("rules");
+    if local.is_dir() {
+        return local.to_path_buf();
+    }
+    config_dir().unwrap_or_else(|| local.to_path_buf())
+}
+
+/// Where runtime errors are appended: `$ORDERLY_LOG_FILE` if set, otherwise
+/// `error.log` in the state directory.
+pub fn error_log_path() -> PathBuf {
+    match env::var_os("ORDERLY_LOG_FILE") {
+        Some(file) => PathBuf::from(file),
+        None => state_dir().join("error.log"),
+    }
+}
+
+/// Joins a relative rule file path onto `base`; absolute paths are kept as they are.
+pub fn resolve(path: &str, base: &Path) -> String {
+    if Path::new(path).is_absolute() {
+        path.to_string()
+    } else {
+        base.join(path).to_string_lossy().into_owned()
+    }
+}
+
+/// Expands `$VAR` / `${VAR}` references and then a leading `~` in a rule file path.
+///
+/// Variables are looked up in `variables` first and then in the environment, and
+/// may themselves refer to other variables. `$$` produces a literal `$`.
+pub fn expand(path: &str, variables: &BTreeMap<String, String>) -> Result<String, String> {
+    let substituted = expand_variables(path, variables, &mut Vec::new())?;
+    expand_home(&substituted)
+}
+
+fn expand_variables(
+    input: &str,
+    variables: &BTreeMap<String, String>,
+    stack: &mut Vec<String>,
+) -> Result<String, String> {
+    let mut output = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            output.push(c);
+            continue;
+        }
+        let name = match chars.peek() {
+            Some('$') => {
+                chars.next();
+                output.push('$');
+                continue;
+            }
+            Some('{') => {
+                chars.next();
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(format!("unterminated `${{` in `{}`", input)),
+                    }
+                }
+                name
+            }
+            Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                name
+            }
+            _ => {
+                output.push('$');
+                continue;
+            }
+        };
+        output.push_str(&lookup(&name, variables, stack)?);
+    }
+    Ok(output)
+}
+
+fn lookup(
+    name: &str,
+    variables: &BTreeMap<String, String>,
+    stack: &mut Vec<String>,
+) -> Result<String, String> {
+    let value = match variables.get(name) {
+        Some(value) => value,
+        None => return env::var(name).map_err(|_| format!("undefined variable `{}`", name)),
+    };
+    if stack.iter().any(|seen| seen == name) {
+        stack.push(name.to_string());
+        return Err(format!("variable cycle: {}", stack.join(" -> ")));
+    }
+    stack.push(name.to_string());
+    let expanded = expand_variables(value, variables, stack)?;
+    stack.pop();
+    Ok(expanded)
+}
+
+/// Replaces a leading `~` (alone or followed by a separator) with the home directory.
+pub fn expand_home(path: &str) -> Result<String, String> {
+    let rest = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') || rest.starts_with('\\') => rest,
+        _ => return Ok(path.to_string()),
+    };
+    let home = home_dir()
+        .ok_or_else(|| format!("cannot expand `~` in `{}`: home directory is unknown", path))?;
+    Ok(format!("{}{}", home.display(), rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn expands_only_leading_tilde() {
+        let home = home_dir().unwrap();
+        let vars = BTreeMap::new();
+        assert_eq!(
+            expand("~/Music", &vars).unwrap(),
+            format!("{}/Music", home.display())
+        );
+        assert_eq!(expand("backup~/a~b", &vars).unwrap(), "backup~/a~b");
+        assert_eq!(expand("~user/x", &vars).unwrap(), "~user/x");
+    }
+
+    #[test]
+    fn expands_user_variables_before_environment() {
+        let vars = variables(&[
+            ("root", "~/Archive"),
+            ("PATH", "shadowed"),
+            ("docs", "${root}/Docs"),
+        ]);
+        let home = home_dir().unwrap();
+        assert_eq!(
+            expand("$docs/$PATH", &vars).unwrap(),
+            format!("{}/Archive/Docs/shadowed", home.display())
+        );
+        assert_eq!(expand("cost$$5", &vars).unwrap(), "cost$5");
+    }
+
+    #[test]
+    fn reports_undefined_and_cyclic_variables() {
+        let vars = variables(&[("a", "$b"), ("b", "${a}")]);
+        assert_eq!(
+            expand("$ORDERLY_SURELY_UNDEFINED", &vars).unwrap_err(),
+            "undefined variable `ORDERLY_SURELY_UNDEFINED`"
+        );
+        assert_eq!(
+            expand("$a", &vars).unwrap_err(),
+            "variable cycle: a -> b -> a"
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use regex::Regex;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// A regular expression, compiled when the rule file loads.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct RegexPattern(pub Regex);
+
+impl RegexPattern {
+    /// The names of the pattern's named capture groups.
+    pub fn group_names(&self) -> impl Iterator<Item = &str> {
+        self.0.capture_names().flatten()
+    }
+}
+
+impl PartialEq for RegexPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl TryFrom<String> for RegexPattern {
+    type Error = String;
+
+    fn try_from(pattern: String) -> Result<Self, String> {
+        Regex::new(&pattern)
+            .map(RegexPattern)
+            .map_err(|e| format!("invalid regex: {}", e))
+    }
+}
+
+impl From<RegexPattern> for String {
+    fn from(pattern: RegexPattern) -> Self {
+        pattern.0.as_str().to_string()
+    }
+}
+
+impl JsonSchema for RegexPattern {
+    fn schema_name() -> String {
+        "RegexPattern".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+/// Calls `visit` with the name of each `{name}` placeholder in `template`. Braces that
+/// don't surround a name are kept as they are.
+fn placeholders<'t>(template: &'t str, mut visit: impl FnMut(usize, usize, &'t str)) {
+    let mut start = 0;
+    while let Some(open) = template[start..].find('{').map(|i| start + i) {
+        let rest = &template[open + 1..];
+        let length = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if length > 0 && rest[length..].starts_with('}') {
+            visit(open, open + length + 2, &rest[..length]);
+            start = open + length + 2;
+        } else {
+            start = open + 1;
+        }
+    }
+}
+
+/// The placeholder names used in `template`.
+pub fn template_names(template: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    placeholders(template, |_, _, name| names.push(name));
+    names
+}
+
+/// Replaces each `{name}` in `template` with the capture of that name.
+pub fn fill(template: &str, captures: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut output = String::with_capacity(template.len());
+    let mut copied = 0;
+    let mut missing = None;
+    placeholders(template, |start, end, name| {
+        output.push_str(&template[copied..start]);
+        match captures.get(name) {
+            Some(value) => output.push_str(value),
+            None => missing = missing.take().or(Some(name)),
+        }
+        copied = end;
+    });
+    if let Some(name) = missing {
+        return Err(format!(
+            "`{}` uses `{{{}}}`, but no regex captured `{}`",
+            template, name, name
+        ));
+    }
+    output.push_str(&template[copied..]);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_named_placeholders() {
+        let mut captures = BTreeMap::new();
+        captures.insert("client".to_string(), "acme".to_string());
+
+        assert_eq!(
+            fill("/docs/Invoices/{client}", &captures),
+            Ok("/docs/Invoices/acme".to_string())
+        );
+        assert_eq!(
+            fill("/docs/{ odd }/{}", &captures),
+            Ok("/docs/{ odd }/{}".to_string())
+        );
+        assert!(fill("/docs/{year}", &captures).is_err());
+        assert_eq!(template_names("{a}/x/{b_2}"), ["a", "b_2"]);
+    }
+}
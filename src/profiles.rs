@@ -0,0 +1,123 @@
+use crate::config::Rule;
+
+/// Adds the variables and folders of the profile `name`, if the file defines it. Runs
+/// before includes are resolved, so profile folders can use snippets and includes too.
+pub fn merge(rule: &mut Rule, name: &str) {
+    let profile = match rule.profiles.get(name) {
+        Some(profile) => profile.clone(),
+        None => return,
+    };
+    rule.variables.extend(profile.variables);
+    for folder in profile.folders {
+        match rule.folders.iter_mut().find(|f| f.path == folder.path) {
+            Some(existing) => *existing = folder,
+            None => rule.folders.push(folder),
+        }
+    }
+}
+
+/// Turns rules and folders on or off as the profile `name` says, then drops everything
+/// that ends up disabled. Runs after includes are resolved, so included rules can be
+/// named as well.
+pub fn prune(rule: &mut Rule, name: Option<&str>) -> Result<(), String> {
+    let Rule {
+        profiles, folders, ..
+    } = rule;
+    if let Some((name, profile)) = name.and_then(|name| Some((name, profiles.get(name)?))) {
+        let enable = profile.enable.iter().map(|target| (target, true));
+        let disable = profile.disable.iter().map(|target| (target, false));
+        for (target, enabled) in enable.chain(disable) {
+            let mut found = false;
+            for folder in folders.iter_mut() {
+                if folder.path == *target {
+                    folder.enabled = Some(enabled);
+                    found = true;
+                }
+                for folder_rule in folder.rules.iter_mut().filter(|r| r.name == *target) {
+                    folder_rule.enabled = Some(enabled);
+                    found = true;
+                }
+            }
+            if !found {
+                return Err(format!(
+                    "profile `{}`: no rule or folder called `{}`",
+                    name, target
+                ));
+            }
+        }
+    }
+    folders.retain(|folder| folder.enabled != Some(false));
+    for folder in folders.iter_mut() {
+        folder.rules.retain(|rule| rule.enabled != Some(false));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    fn folder_name(path: &str) -> &str {
+        Path::new(path).file_name().unwrap().to_str().unwrap()
+    }
+
+    #[test]
+    fn profile_overrides_variables_folders_and_rules() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rules.yaml");
+        fs::write(
+            &path,
+            r#"version: 2
+name: Home
+description: x
+variables:
+  inbox: home
+profiles:
+  work:
+    variables:
+      inbox: work
+    enable: [Archive]
+    disable: [Desktop]
+    folders:
+    - path: scans
+      rules: []
+folders:
+- path: $inbox
+  rules:
+  - name: Archive
+    enabled: false
+    conditions: []
+    actions: []
+  - name: Desktop
+    conditions: []
+    actions: []
+"#,
+        )
+        .unwrap();
+
+        let home = config::load_config(&path, None).unwrap();
+        let work = config::load_config(&path, Some("work")).unwrap();
+
+        let names = |rule_set: &config::Rule| -> Vec<String> {
+            rule_set
+                .folders
+                .iter()
+                .flat_map(|f| {
+                    f.rules
+                        .iter()
+                        .map(move |r| format!("{}:{}", folder_name(&f.path), r.name))
+                })
+                .collect()
+        };
+        assert_eq!(names(&home), ["home:Desktop"]);
+        assert_eq!(names(&work), ["work:Archive"]);
+        assert_eq!(folder_name(&work.folders[1].path), "scans");
+        let err = config::load_rules(&path, Some("travel")).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("no rule file defines profile `travel`"));
+    }
+}
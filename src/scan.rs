@@ -0,0 +1,177 @@
+use crate::config::Folder;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::warn;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Decides which entries under a watched folder get organized, from the folder's
+/// scan options. Used by both the scanner and the watcher so they agree.
+pub struct Scanner {
+    root: PathBuf,
+    /// Deepest level scanned, counting entries directly in the folder as 1.
+    depth: usize,
+    include_hidden: bool,
+    follow_symlinks: bool,
+    /// Exclude patterns matched against names, and against relative paths.
+    names: GlobSet,
+    paths: GlobSet,
+}
+
+impl Scanner {
+    pub fn new(folder: &Folder) -> Result<Scanner, String> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        for pattern in &folder.exclude {
+            let glob = Glob::new(pattern).map_err(|e| format!("invalid exclude pattern: {}", e))?;
+            if pattern.contains('/') {
+                paths.add(glob);
+            } else {
+                names.add(glob);
+            }
+        }
+        let recursive = folder.recursive || folder.max_depth.is_some();
+        Ok(Scanner {
+            root: PathBuf::from(&folder.path),
+            depth: match folder.max_depth {
+                _ if !recursive => 1,
+                Some(max_depth) => max_depth.saturating_add(1),
+                None => usize::MAX,
+            },
+            include_hidden: folder.include_hidden,
+            follow_symlinks: folder.follow_symlinks,
+            names: names.build().map_err(|e| e.to_string())?,
+            paths: paths.build().map_err(|e| e.to_string())?,
+        })
+    }
+
+    /// Whether files in subfolders are scanned too, which `max_depth: 0` rules out.
+    pub fn is_recursive(&self) -> bool {
+        self.depth > 1
+    }
+
+    /// The files rules look at, sorted by name. Subfolders are never listed themselves,
+    /// so a rule can't move a whole folder along with everything inside it.
+    pub fn entries(&self) -> Result<Vec<PathBuf>, String> {
+        let walker = WalkDir::new(&self.root)
+            .min_depth(1)
+            .max_depth(self.depth)
+            .follow_links(self.follow_symlinks)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| !self.skips(self.relative(entry.path())));
+
+        let mut entries = Vec::new();
+        for entry in walker {
+            match entry {
+                Ok(entry) if entry.path().is_dir() => {}
+                Ok(entry) => entries.push(entry.into_path()),
+                Err(e) if e.depth() == 0 => {
+                    return Err(format!(
+                        "Failed to read directory {}: {}",
+                        self.root.display(),
+                        e
+                    ))
+                }
+                Err(e) => warn!("Skipping unreadable entry: {}", e),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Whether a change at `path`, as reported by the watcher, is something the scan
+    /// would pick up.
+    pub fn covers(&self, path: &Path) -> bool {
+        // Events name paths the way the folder was watched, or canonically.
+        let canonical_root = self.root.canonicalize().ok();
+        let relative = path
+            .strip_prefix(&self.root)
+            .ok()
+            .or_else(|| path.strip_prefix(canonical_root.as_ref()?).ok());
+        let relative = match relative {
+            Some(relative) => relative,
+            None => return false,
+        };
+        let depth = relative.components().count();
+        depth > 0 && depth <= self.depth && !self.skips(relative)
+    }
+
+    fn relative<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.root).unwrap_or(path)
+    }
+
+    /// Whether `relative` is hidden or excluded, or inside a folder that is.
+    fn skips(&self, relative: &Path) -> bool {
+        let hidden = |part: &Path| {
+            part.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        };
+        relative
+            .ancestors()
+            .filter(|part| !part.as_os_str().is_empty())
+            .any(|part| {
+                (!self.include_hidden && hidden(part))
+                    || self.paths.is_match(part)
+                    || part
+                        .file_name()
+                        .is_some_and(|name| self.names.is_match(name))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn scans_to_max_depth_skipping_hidden_and_excluded_entries() {
+        let dir = tempdir().unwrap();
+        for file in [
+            "a.txt",
+            ".hidden",
+            "one/b.txt",
+            "one/two/c.txt",
+            "one/two/three/d.txt",
+            "node_modules/e.txt",
+            "one/skip.tmp",
+            "one/two/f.log",
+            "empty/",
+        ] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            if !file.ends_with('/') {
+                fs::write(path, "").unwrap();
+            }
+        }
+        let folder = Folder {
+            path: dir.path().to_str().unwrap().into(),
+            max_depth: Some(2),
+            exclude: vec![
+                "node_modules".into(),
+                "*.tmp".into(),
+                "one/two/*.log".into(),
+            ],
+            ..Folder::default()
+        };
+        let scanner = Scanner::new(&folder).unwrap();
+
+        let entries = scanner.entries().unwrap();
+
+        let relative: Vec<_> = entries
+            .iter()
+            .map(|path| path.strip_prefix(dir.path()).unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(relative, ["a.txt", "one/b.txt", "one/two/c.txt"]);
+        assert!(scanner.covers(&dir.path().join("one/two/c.txt")));
+        assert!(!scanner.covers(&dir.path().join("one/two/three/d.txt")));
+        assert!(!scanner.covers(&dir.path().join("node_modules/x/y")));
+        assert!(!scanner.covers(&dir.path().join(".hidden")));
+        let flat = Scanner::new(&Folder {
+            path: folder.path.clone(),
+            ..Folder::default()
+        })
+        .unwrap();
+        assert_eq!(flat.entries().unwrap(), [dir.path().join("a.txt")]);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::config::Schedule;
+use chrono::{DateTime, Datelike, Duration, Local, NaiveTime, Timelike, Weekday};
+use croner::Cron;
+use std::fmt;
+
+/// A parsed `Schedule`.
+pub struct Window {
+    cron: Option<Cron>,
+    /// Allowed weekdays, indexed from Monday; all of them when `days` is empty.
+    days: [bool; 7],
+    from: Option<NaiveTime>,
+    to: Option<NaiveTime>,
+}
+
+impl Window {
+    pub fn new(schedule: &Schedule) -> Result<Window, String> {
+        let cron = match &schedule.cron {
+            Some(pattern) => Some(
+                Cron::new(pattern)
+                    .parse()
+                    .map_err(|e| format!("invalid cron pattern `{}`: {}", pattern, e))?,
+            ),
+            None => None,
+        };
+        let mut days = [schedule.days.is_empty(); 7];
+        for day in &schedule.days {
+            let (first, last) = match day.split_once('-') {
+                Some((first, last)) => (weekday(first)?, weekday(last)?),
+                None => (weekday(day)?, weekday(day)?),
+            };
+            let mut current = first;
+            days[current.num_days_from_monday() as usize] = true;
+            while current != last {
+                current = current.succ();
+                days[current.num_days_from_monday() as usize] = true;
+            }
+        }
+        let from = schedule.from.as_deref().map(time).transpose()?;
+        let to = schedule.to.as_deref().map(time).transpose()?;
+        if from.is_some() && from == to {
+            return Err("`from` and `to` are the same time".into());
+        }
+        Ok(Window {
+            cron,
+            days,
+            from,
+            to,
+        })
+    }
+
+    /// Whether `now` falls inside the window. The `days` of a range that wraps past
+    /// midnight are the days it starts on.
+    pub fn contains(&self, now: &DateTime<Local>) -> bool {
+        if let Some(cron) = &self.cron {
+            // Patterns name minutes, so ignore how far into the minute we are.
+            let minute = now.with_second(0).and_then(|t| t.with_nanosecond(0));
+            let matching = minute.is_some_and(|t| cron.is_time_matching(&t).unwrap_or(false));
+            if !matching {
+                return false;
+            }
+        }
+        let time = now.time();
+        let (in_range, from_yesterday) = match (self.from, self.to) {
+            (Some(from), Some(to)) if from < to => (from <= time && time < to, false),
+            (Some(from), Some(to)) => (time >= from || time < to, time < to),
+            (Some(from), None) => (time >= from, false),
+            (None, Some(to)) => (time < to, false),
+            (None, None) => (true, false),
+        };
+        let day = if from_yesterday {
+            (now.date_naive() - Duration::days(1)).weekday()
+        } else {
+            now.weekday()
+        };
+        in_range && self.days[day.num_days_from_monday() as usize]
+    }
+}
+
+/// Checks that both windows parse.
+pub fn validate(
+    active_when: &Option<Schedule>,
+    inactive_when: &Option<Schedule>,
+) -> Result<(), String> {
+    for (field, schedule) in [
+        ("active_when", active_when),
+        ("inactive_when", inactive_when),
+    ] {
+        if let Some(schedule) = schedule {
+            Window::new(schedule).map_err(|e| format!("`{}`: {}", field, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Why something with these windows is skipped at `now`, or `None` if it runs.
+pub fn skip_reason(
+    active_when: &Option<Schedule>,
+    inactive_when: &Option<Schedule>,
+    now: &DateTime<Local>,
+) -> Option<String> {
+    let contains = |schedule: &Schedule| Window::new(schedule).map(|w| w.contains(now));
+    if let Some(schedule) = active_when {
+        match contains(schedule) {
+            Ok(true) => {}
+            Ok(false) => return Some(format!("outside `active_when` ({})", schedule)),
+            Err(e) => return Some(e),
+        }
+    }
+    if let Some(schedule) = inactive_when {
+        match contains(schedule) {
+            Ok(false) => {}
+            Ok(true) => return Some(format!("inside `inactive_when` ({})", schedule)),
+            Err(e) => return Some(e),
+        }
+    }
+    None
+}
+
+fn weekday(name: &str) -> Result<Weekday, String> {
+    name.trim()
+        .parse()
+        .map_err(|_| format!("unknown weekday `{}`", name))
+}
+
+fn time(text: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(text, "%H:%M")
+        .map_err(|_| format!("invalid time `{}`, expected HH:MM", text))
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(cron) = &self.cron {
+            parts.push(format!("cron `{}`", cron));
+        }
+        if !self.days.is_empty() {
+            parts.push(self.days.join(", "));
+        }
+        match (&self.from, &self.to) {
+            (Some(from), Some(to)) => parts.push(format!("{}-{}", from, to)),
+            (Some(from), None) => parts.push(format!("from {}", from)),
+            (None, Some(to)) => parts.push(format!("until {}", to)),
+            (None, None) => {}
+        }
+        if parts.is_empty() {
+            parts.push("always".into());
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        // 2024-06-03 is a Monday.
+        Local
+            .with_ymd_and_hms(2024, 6, day, hour, minute, 30)
+            .unwrap()
+    }
+
+    #[test]
+    fn matches_days_time_ranges_and_cron_patterns() {
+        let working_hours = Schedule {
+            days: vec!["mon-fri".into()],
+            from: Some("09:00".into()),
+            to: Some("17:00".into()),
+            ..Schedule::default()
+        };
+        let friday_nights = Schedule {
+            days: vec!["fri".into()],
+            from: Some("22:00".into()),
+            to: Some("02:00".into()),
+            ..Schedule::default()
+        };
+        let evenings = Schedule {
+            cron: Some("* 18-23 * * *".into()),
+            ..Schedule::default()
+        };
+
+        assert_eq!(
+            skip_reason(&None, &Some(working_hours.clone()), &at(3, 8, 59)),
+            None
+        );
+        assert_eq!(
+            skip_reason(&None, &Some(working_hours.clone()), &at(5, 12, 0)).unwrap(),
+            "inside `inactive_when` (mon-fri, 09:00-17:00)"
+        );
+        assert_eq!(
+            skip_reason(&None, &Some(working_hours), &at(8, 12, 0)),
+            None
+        );
+        let friday_nights = Window::new(&friday_nights).unwrap();
+        assert!(friday_nights.contains(&at(7, 23, 0)));
+        assert!(friday_nights.contains(&at(8, 1, 0)));
+        assert!(!friday_nights.contains(&at(7, 1, 0)));
+        assert!(Window::new(&evenings).unwrap().contains(&at(4, 18, 5)));
+        assert!(!Window::new(&evenings).unwrap().contains(&at(4, 17, 59)));
+        assert!(validate(
+            &Some(Schedule {
+                days: vec!["someday".into()],
+                ..Schedule::default()
+            }),
+            &None
+        )
+        .is_err());
+    }
+}
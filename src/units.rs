@@ -0,0 +1,296 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// A number of bytes, written as a plain number or with a unit such as `500k`, `10MB`
+/// or `1.5GiB`. Units with an `i` count in powers of 1024, the others in powers of 1000.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "SizeValue", into = "SizeValue")]
+pub struct ByteSize {
+    pub bytes: u64,
+    /// How the size was written, so it is saved back the same way.
+    text: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum SizeValue {
+    Bytes(u64),
+    Text(String),
+}
+
+impl ByteSize {
+    pub fn parse(text: &str) -> Result<ByteSize, String> {
+        let text = text.trim();
+        let split = text
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(text.len());
+        let (number, unit) = text.split_at(split);
+        let number: f64 = number
+            .parse()
+            .map_err(|_| format!("invalid size `{}`, expected e.g. `10MB`", text))?;
+        let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kb" => 1_000,
+            "m" | "mb" => 1_000_000,
+            "g" | "gb" => 1_000_000_000,
+            "t" | "tb" => 1_000_000_000_000,
+            "ki" | "kib" => 1 << 10,
+            "mi" | "mib" => 1 << 20,
+            "gi" | "gib" => 1 << 30,
+            "ti" | "tib" => 1 << 40,
+            _ => return Err(format!("unknown size unit in `{}`", text)),
+        };
+        let bytes = (number * multiplier as f64).round();
+        if bytes >= u64::MAX as f64 {
+            return Err(format!("size `{}` is too large", text));
+        }
+        Ok(ByteSize {
+            bytes: bytes as u64,
+            text: Some(text.to_string()),
+        })
+    }
+}
+
+impl From<u64> for ByteSize {
+    fn from(bytes: u64) -> Self {
+        ByteSize { bytes, text: None }
+    }
+}
+
+impl TryFrom<SizeValue> for ByteSize {
+    type Error = String;
+
+    fn try_from(value: SizeValue) -> Result<Self, String> {
+        match value {
+            SizeValue::Bytes(bytes) => Ok(ByteSize::from(bytes)),
+            SizeValue::Text(text) => ByteSize::parse(&text),
+        }
+    }
+}
+
+impl From<ByteSize> for SizeValue {
+    fn from(size: ByteSize) -> Self {
+        match size.text {
+            Some(text) => SizeValue::Text(text),
+            None => SizeValue::Bytes(size.bytes),
+        }
+    }
+}
+
+impl JsonSchema for ByteSize {
+    fn schema_name() -> String {
+        "ByteSize".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        SizeValue::json_schema(gen)
+    }
+}
+
+/// Two sizes written as `[low, high]`, where `low` is no larger than `high`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "[ByteSize; 2]", into = "[ByteSize; 2]")]
+pub struct SizeBounds {
+    pub low: ByteSize,
+    pub high: ByteSize,
+}
+
+impl TryFrom<[ByteSize; 2]> for SizeBounds {
+    type Error = String;
+
+    fn try_from([low, high]: [ByteSize; 2]) -> Result<Self, String> {
+        if low.bytes > high.bytes {
+            return Err(format!(
+                "size range starts at {} bytes, above its end at {} bytes",
+                low.bytes, high.bytes
+            ));
+        }
+        Ok(SizeBounds { low, high })
+    }
+}
+
+impl From<SizeBounds> for [ByteSize; 2] {
+    fn from(bounds: SizeBounds) -> Self {
+        [bounds.low, bounds.high]
+    }
+}
+
+impl JsonSchema for SizeBounds {
+    fn schema_name() -> String {
+        "SizeBounds".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        <[ByteSize; 2]>::json_schema(gen)
+    }
+}
+
+/// A length of time such as `30d`, `2h` or `1w2d`, made of numbers with the units `s`,
+/// `m` (or `min`), `h`, `d` and `w`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Age {
+    pub seconds: u64,
+    text: String,
+}
+
+impl Age {
+    pub fn parse(text: &str) -> Result<Age, String> {
+        let invalid = || format!("invalid age `{}`, expected e.g. `30d` or `2h`", text);
+        let mut seconds: u64 = 0;
+        let mut rest = text.trim();
+        if rest.is_empty() {
+            return Err(invalid());
+        }
+        while !rest.is_empty() {
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .ok_or_else(invalid)?;
+            let number: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+            rest = rest[digits..].trim_start();
+            let unit_len = rest
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(rest.len());
+            let unit = match &rest[..unit_len] {
+                "s" => 1,
+                "m" | "min" => 60,
+                "h" => 60 * 60,
+                "d" => 24 * 60 * 60,
+                "w" => 7 * 24 * 60 * 60,
+                _ => return Err(invalid()),
+            };
+            seconds = number
+                .checked_mul(unit)
+                .and_then(|part| seconds.checked_add(part))
+                .ok_or_else(|| format!("age `{}` is too long", text))?;
+            rest = rest[unit_len..].trim_start();
+        }
+        Ok(Age {
+            seconds,
+            text: text.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for Age {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        Age::parse(&text)
+    }
+}
+
+impl From<Age> for String {
+    fn from(age: Age) -> Self {
+        age.text
+    }
+}
+
+impl JsonSchema for Age {
+    fn schema_name() -> String {
+        "Age".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+/// A local date, `2024-01-31`, or time, `2024-01-31 18:00` or `2024-01-31T18:00:30`,
+/// standing for the whole day, minute or second it names.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Moment {
+    pub start: NaiveDateTime,
+    /// The first instant after the moment.
+    pub end: NaiveDateTime,
+    text: String,
+}
+
+impl Moment {
+    pub fn parse(text: &str) -> Result<Moment, String> {
+        let trimmed = text.trim();
+        let (start, length) = if let Ok(date) = NaiveDate::parse_from_str(trimmed, "%Y-%m-%d") {
+            (date.and_hms_opt(0, 0, 0).unwrap(), Duration::days(1))
+        } else if let Some(time) = ["%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"]
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(trimmed, format).ok())
+        {
+            (time, Duration::minutes(1))
+        } else if let Some(time) = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"]
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(trimmed, format).ok())
+        {
+            (time, Duration::seconds(1))
+        } else {
+            return Err(format!(
+                "invalid date `{}`, expected e.g. `2024-01-31` or `2024-01-31 18:00`",
+                text
+            ));
+        };
+        Ok(Moment {
+            start,
+            end: start + length,
+            text: text.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for Moment {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        Moment::parse(&text)
+    }
+}
+
+impl From<Moment> for String {
+    fn from(moment: Moment) -> Self {
+        moment.text
+    }
+}
+
+impl JsonSchema for Moment {
+    fn schema_name() -> String {
+        "Moment".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_decimal_and_binary_units() {
+        let bytes = |text| ByteSize::parse(text).map(|size| size.bytes);
+
+        assert_eq!(bytes("500k"), Ok(500_000));
+        assert_eq!(bytes("10MB"), Ok(10_000_000));
+        assert_eq!(bytes("1.5GiB"), Ok(1_610_612_736));
+        assert_eq!(bytes("200 mb"), Ok(200_000_000));
+        assert_eq!(bytes("42"), Ok(42));
+        assert!(bytes("10 parsecs").is_err());
+        assert!(bytes("MB").is_err());
+    }
+
+    #[test]
+    fn parses_ages_and_moments() {
+        assert_eq!(Age::parse("30d").unwrap().seconds, 30 * 86_400);
+        assert_eq!(Age::parse("1w 2h").unwrap().seconds, 7 * 86_400 + 7_200);
+        assert!(Age::parse("3 months").is_err());
+
+        let day = Moment::parse("2024-01-31").unwrap();
+        assert_eq!(day.end - day.start, Duration::days(1));
+        let minute = Moment::parse("2024-01-31 18:00").unwrap();
+        assert_eq!(minute.end - minute.start, Duration::minutes(1));
+        assert!(Moment::parse("31/01/2024").is_err());
+    }
+}